@@ -3,31 +3,232 @@ extern crate gtk;
 
 use cairo::Context;
 use gtk::prelude::*;
-use gtk::DrawingArea;
-use std::fs;
+use gtk::{gdk, glib, DrawingArea};
+use std::{cell::RefCell, fs::File, io::{ErrorKind, Read}, path::Path, rc::Rc, sync::mpsc::Receiver};
 
-use parse::{css, html, style};
-use render::renderer::Renderer;
+use parse::{
+    dom::{self, NodeId},
+    events::Event,
+    html,
+    mutation::MutationRecord,
+    style, stylesheets,
+};
+use render::renderer::{HitRegions, Renderer};
 
 pub mod parse;
 pub mod render;
 
+const HTML_PATH: &str = "./examples/test.html";
+
+/// The document being shown, and the changes made to it since it was last
+/// drawn.
+struct Page {
+    document: dom::Document,
+    changes: Receiver<MutationRecord>,
+}
+
+impl Page {
+    fn new(mut document: dom::Document) -> Page {
+        let changes = document.observe();
+        Page { document, changes }
+    }
+}
+
+/// Where input goes: the element the pointer was pressed on, and the
+/// element with keyboard focus.
+#[derive(Default)]
+struct Input {
+    pressed: Option<NodeId>,
+    focused: Option<NodeId>,
+}
+
 fn build_ui(application: &gtk::Application) {
-    drawable(application, 500, 500, |_, cr| {
-        let html_string = fs::read_to_string("./examples/test.html").expect("Failed to read file");
-        let node_tree = html::parse(html_string);
+    let page: Rc<RefCell<Option<Page>>> = Rc::new(RefCell::new(None));
+    let preview: Rc<RefCell<Option<dom::Document>>> = Rc::new(RefCell::new(None));
+    let hit_regions: Rc<RefCell<HitRegions>> = Rc::new(RefCell::new(HitRegions::default()));
+
+    let (drawn_page, drawn_preview) = (Rc::clone(&page), Rc::clone(&preview));
+    let drawn_regions = Rc::clone(&hit_regions);
+    let drawing_area = drawable(application, 500, 500, move |_, cr| {
+        let drawn_page = drawn_page.borrow();
+        let drawn_preview = drawn_preview.borrow();
+        let dom_tree = match (&*drawn_page, &*drawn_preview) {
+            (Some(Page { document, changes }), _) => {
+                changes.try_iter().for_each(drop);
+                document
+            }
+            (None, Some(document)) => document,
+            (None, None) => return Inhibit(false),
+        };
 
-        let css_string = fs::read_to_string("./examples/test.css").expect("Failed to read file");
-        let stylesheet = css::parse(css_string);
+        let (stylesheets, stylesheet_errors) = stylesheets::load(dom_tree, Path::new(HTML_PATH));
+        for error in stylesheet_errors {
+            eprintln!("{}", error);
+        }
 
-        let style_tree = style::style_tree(&node_tree, &stylesheet, None);
+        let style_tree = style::style_tree(dom_tree, &stylesheets);
 
-        let mut renderer = Renderer::new(cr, 500, 500);
+        let renderer = Renderer::new(cr, 500, 500);
 
-        renderer.draw(style_tree);
+        // A preview goes away once the page has loaded, so nothing in it can
+        // be a target for input.
+        let regions = renderer.draw(style_tree);
+        *drawn_regions.borrow_mut() = if drawn_page.is_some() { regions } else { HitRegions::default() };
 
         Inhibit(false)
     });
+
+    connect_input(&drawing_area, &page, &hit_regions);
+
+    // Parse a chunk at a time between redraws, so a preview of the page
+    // shows up while the rest of it is still loading. Each preview is a copy
+    // of everything parsed so far, so they're taken each time the amount
+    // read doubles rather than after every chunk. The page itself, which
+    // input goes to, is only made once, from the finished document.
+    let mut file = File::open(HTML_PATH).expect("Failed to read file");
+    let mut parser = Some(html::StreamingParser::new(None, Some(HTML_PATH)));
+    let mut buffer = [0; 8192];
+    let (mut loaded, mut next_preview) = (0, buffer.len());
+    glib::idle_add_local(move || {
+        let Some(mut streaming_parser) = parser.take() else {
+            return glib::Continue(false);
+        };
+        match file.read(&mut buffer) {
+            Ok(0) => {}
+            Ok(read) => {
+                streaming_parser.push_bytes(&buffer[..read]);
+                loaded += read;
+                if loaded >= next_preview {
+                    *preview.borrow_mut() = Some(streaming_parser.document().into());
+                    next_preview = loaded * 2;
+                    drawing_area.queue_draw();
+                }
+                parser = Some(streaming_parser);
+                return glib::Continue(true);
+            }
+            Err(error) if error.kind() == ErrorKind::Interrupted => {
+                parser = Some(streaming_parser);
+                return glib::Continue(true);
+            }
+            Err(error) => eprintln!("Failed to read {}: {}", HTML_PATH, error),
+        }
+        *preview.borrow_mut() = None;
+        *page.borrow_mut() = Some(Page::new(streaming_parser.finish().0.into()));
+        drawing_area.queue_draw();
+        glib::Continue(false)
+    });
+}
+
+/// Turns pointer and keyboard input into DOM events. Pointer events go to
+/// the element under the pointer, and keyboard events to the element last
+/// clicked, or the body if there isn't one.
+fn connect_input(drawing_area: &DrawingArea, page: &Rc<RefCell<Option<Page>>>, hit_regions: &Rc<RefCell<HitRegions>>) {
+    let input = Rc::new(RefCell::new(Input::default()));
+    drawing_area.add_events(
+        gdk::EventMask::POINTER_MOTION_MASK
+            | gdk::EventMask::BUTTON_PRESS_MASK
+            | gdk::EventMask::BUTTON_RELEASE_MASK
+            | gdk::EventMask::KEY_PRESS_MASK
+            | gdk::EventMask::KEY_RELEASE_MASK,
+    );
+    drawing_area.set_can_focus(true);
+    drawing_area.grab_focus();
+
+    let (motion_page, motion_regions) = (Rc::clone(page), Rc::clone(hit_regions));
+    drawing_area.connect_motion_notify_event(move |drawing_area, motion| {
+        let (x, y) = motion.position();
+        let target = motion_regions.borrow().node_at(x, y);
+        dispatch(drawing_area, &motion_page, target, Event::mouse("mousemove", x, y, 0));
+        Inhibit(false)
+    });
+
+    let (press_page, press_regions, press_input) = (Rc::clone(page), Rc::clone(hit_regions), Rc::clone(&input));
+    drawing_area.connect_button_press_event(move |drawing_area, press| {
+        if press.event_type() != gdk::EventType::ButtonPress {
+            return Inhibit(false);
+        }
+        let (x, y) = press.position();
+        let target = press_regions.borrow().node_at(x, y);
+        *press_input.borrow_mut() = Input { pressed: target, focused: target };
+        dispatch(drawing_area, &press_page, target, Event::mouse("mousedown", x, y, dom_button(press.button())));
+        Inhibit(false)
+    });
+
+    let (release_page, release_regions, release_input) = (Rc::clone(page), Rc::clone(hit_regions), Rc::clone(&input));
+    drawing_area.connect_button_release_event(move |drawing_area, release| {
+        let (x, y) = release.position();
+        let button = dom_button(release.button());
+        let target = release_regions.borrow().node_at(x, y);
+        let pressed = release_input.borrow_mut().pressed.take();
+        dispatch(drawing_area, &release_page, target, Event::mouse("mouseup", x, y, button));
+        if target.is_some() && target == pressed {
+            dispatch(drawing_area, &release_page, target, Event::mouse("click", x, y, button));
+        }
+        Inhibit(false)
+    });
+
+    let (key_page, key_input) = (Rc::clone(page), Rc::clone(&input));
+    drawing_area.connect_key_press_event(move |drawing_area, key| {
+        let target = focused_node(&key_page, &key_input);
+        dispatch(drawing_area, &key_page, target, Event::key("keydown", &dom_key(key.keyval())));
+        Inhibit(false)
+    });
+
+    let (key_page, key_input) = (Rc::clone(page), input);
+    drawing_area.connect_key_release_event(move |drawing_area, key| {
+        let target = focused_node(&key_page, &key_input);
+        dispatch(drawing_area, &key_page, target, Event::key("keyup", &dom_key(key.keyval())));
+        Inhibit(false)
+    });
+}
+
+/// Dispatches `event` to `target`, redrawing if a listener changed the page.
+fn dispatch(drawing_area: &DrawingArea, page: &RefCell<Option<Page>>, target: Option<NodeId>, mut event: Event) {
+    let mut page = page.borrow_mut();
+    let (Some(page), Some(target)) = (page.as_mut(), target) else {
+        return;
+    };
+    page.document.dispatch_event(target, &mut event);
+    if page.changes.try_iter().next().is_some() {
+        drawing_area.queue_draw();
+    }
+}
+
+fn focused_node(page: &RefCell<Option<Page>>, input: &RefCell<Input>) -> Option<NodeId> {
+    let page = page.borrow();
+    let document = &page.as_ref()?.document;
+    input.borrow().focused.or_else(|| document.get_elements_by_tag_name(document.root(), "body").first().copied())
+}
+
+/// GTK numbers mouse buttons from 1, with the right button 3. The DOM
+/// numbers them from 0, with the right button 2.
+fn dom_button(button: u32) -> u32 {
+    match button {
+        1 => 0,
+        2 => 1,
+        3 => 2,
+        8 => 3,
+        9 => 4,
+        other => other,
+    }
+}
+
+/// The DOM names keys by the character they type, or a name like `Enter`
+/// for those that don't type one.
+fn dom_key(key: gdk::keys::Key) -> String {
+    match key.to_unicode().filter(|c| !c.is_control()) {
+        Some(c) => c.to_string(),
+        None => match key.name().as_deref() {
+            Some("Return") | Some("KP_Enter") => "Enter".to_string(),
+            Some("BackSpace") => "Backspace".to_string(),
+            Some("Left") => "ArrowLeft".to_string(),
+            Some("Right") => "ArrowRight".to_string(),
+            Some("Up") => "ArrowUp".to_string(),
+            Some("Down") => "ArrowDown".to_string(),
+            Some(name) => name.to_string(),
+            None => "Unidentified".to_string(),
+        },
+    }
 }
 
 fn main() {
@@ -38,7 +239,7 @@ fn main() {
     application.run();
 }
 
-pub fn drawable<F>(application: &gtk::Application, width: i32, height: i32, draw_fn: F)
+pub fn drawable<F>(application: &gtk::Application, width: i32, height: i32, draw_fn: F) -> DrawingArea
 where
     F: Fn(&DrawingArea, &Context) -> Inhibit + 'static,
 {
@@ -51,4 +252,6 @@ where
 
     window.add(&drawing_area);
     window.show_all();
+
+    drawing_area
 }
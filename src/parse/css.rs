@@ -2,71 +2,168 @@ use std::cmp;
 
 use crate::parse::{
     cssom::{Declaration, Rule, Selector, SimpleSelector, Value, Unit, Color, StyleSheet},
+    error::{ParseError, ParseResult},
     parser::Parser,
+    span::Location,
 };
 
 pub fn parse(source: String) -> StyleSheet {
-	let mut parser = CSSParser::new(source);
-	let rules = parser.parse_rules();
-	StyleSheet { rules }
+	parse_with_errors(source, None).0
+}
+
+/// Like `parse`, but records `file_name` in the span of every rule,
+/// selector and declaration.
+pub fn parse_named(source: String, file_name: &str) -> StyleSheet {
+	parse_with_errors(source, Some(file_name)).0
+}
+
+/// Parses a stylesheet, recovering from invalid input the way the CSS
+/// Syntax spec does: a bad declaration is dropped up to the next `;` or
+/// `}`, and a bad selector list or unsupported at-rule drops that rule.
+/// Everything that was skipped is reported in the returned errors.
+pub fn parse_with_errors(source: String, file_name: Option<&str>) -> (StyleSheet, Vec<ParseError>) {
+	parse_at(source, file_name, Location::default())
+}
+
+/// Like `parse_with_errors`, for a stylesheet that starts at `origin` in
+/// `file_name`, such as the contents of a `<style>` element.
+pub fn parse_at(source: String, file_name: Option<&str>, origin: Location) -> (StyleSheet, Vec<ParseError>) {
+	let parser = match file_name {
+		Some(file_name) => Parser::new(0, source).with_file(file_name),
+		None => Parser::new(0, source),
+	};
+	let parser = parser.with_origin(origin);
+	let mut css_parser = CSSParser::new(parser);
+	let rules = css_parser.parse_rules();
+	(StyleSheet { rules }, css_parser.errors)
+}
+
+/// Parses a bare list of declarations with no selector or braces, like the
+/// value of a `style` attribute.
+pub fn parse_declarations(source: String) -> Vec<Declaration> {
+	parse_declarations_with_errors(source, None).0
+}
+
+/// Like `parse_declarations`, recovering from invalid declarations the same
+/// way `parse_with_errors` does inside a rule.
+pub fn parse_declarations_with_errors(source: String, file_name: Option<&str>) -> (Vec<Declaration>, Vec<ParseError>) {
+	let parser = match file_name {
+		Some(file_name) => Parser::new(0, source).with_file(file_name),
+		None => Parser::new(0, source),
+	};
+	let mut css_parser = CSSParser::new(parser);
+	let declarations = css_parser.parse_declaration_list(false);
+	(declarations, css_parser.errors)
+}
+
+/// Parses a comma-separated selector list on its own, like the argument to
+/// `Document::query_selector`.
+pub fn parse_selectors(source: String) -> ParseResult<Vec<Selector>> {
+	let mut css_parser = CSSParser::new(Parser::new(0, source));
+	css_parser.consume_whitespace();
+	css_parser.parse_selectors(false)
 }
 
 struct CSSParser {
     parser: Parser,
+    errors: Vec<ParseError>,
 }
 
 impl CSSParser {
-    fn new(input: String) -> CSSParser {
+    fn new(parser: Parser) -> CSSParser {
         CSSParser {
-            parser: Parser::new(0, input),
+            parser,
+            errors: Vec::new(),
         }
     }
 
     fn parse_rules(&mut self) -> Vec<Rule> {
         let mut rules = Vec::new();
         loop {
-            self.parser.consume_whitespace();
+            self.consume_whitespace();
             if self.parser.ended() {
                 break;
             }
-            rules.push(self.parse_rule());
+            if self.parser.next_char() == Some('@') {
+                self.skip_at_rule();
+                continue;
+            }
+            if let Some(rule) = self.parse_rule() {
+                rules.push(rule);
+            }
         }
         rules
     }
 
-    fn parse_rule(&mut self) -> Rule {
-        Rule {
-            selectors: self.parse_selectors(),
-            declarations: self.parse_declarations(),
+    fn parse_rule(&mut self) -> Option<Rule> {
+        let start = self.parser.location();
+        match self.parse_selectors(true) {
+            Ok(selectors) => {
+                self.parser.consume_char();
+                let declarations = self.parse_declaration_list(true);
+                Some(Rule {
+                    selectors,
+                    declarations,
+                    span: self.parser.span_from(start),
+                })
+            }
+            Err(error) => {
+                self.errors.push(error);
+                self.skip_qualified_rule();
+                None
+            }
         }
     }
 
-    fn parse_selectors(&mut self) -> Vec<Selector> {
+    /// Parses selectors up to the `{` that opens a rule's block, or to the
+    /// end of the input if they aren't `in_rule`.
+    fn parse_selectors(&mut self, in_rule: bool) -> ParseResult<Vec<Selector>> {
         let mut selectors = Vec::new();
         loop {
-            selectors.push(Selector::Simple(self.parse_simple_selector()));
-            self.parser.consume_whitespace();
+            let start = self.parser.location();
+            let simple = self.parse_simple_selector();
+            if simple.span.byte_range().is_empty() {
+                return Err(self.parser.unexpected("selector"));
+            }
+            let mut selector = Selector::Simple(simple);
+            self.consume_whitespace();
+            // Another simple selector after whitespace is a descendant of the
+            // ones before it.
+            while self.parser.next_char().is_some_and(|c| matches!(c, '#' | '.' | '*') || valid_identifier_char(c)) {
+                let subject = self.parse_simple_selector();
+                selector = Selector::Descendant {
+                    ancestor: Box::new(selector),
+                    subject,
+                    span: self.parser.span_from(start),
+                };
+                self.consume_whitespace();
+            }
+            selectors.push(selector);
             match self.parser.next_char() {
-                ',' => {
+                Some(',') => {
                     self.parser.consume_char();
-                    self.parser.consume_whitespace();
+                    self.consume_whitespace();
                 }
-                '{' => break,
-                c => panic!("Unexpected character {} in selector list", c),
+                Some('{') if in_rule => break,
+                None if !in_rule => break,
+                _ if in_rule => return Err(self.parser.unexpected("',' or '{' in selector list")),
+                _ => return Err(self.parser.unexpected("',' in selector list")),
             }
         }
-				selectors.sort_by_key(|b| cmp::Reverse(b.specificity()));
-        selectors
+        selectors.sort_by_key(|b| cmp::Reverse(b.specificity()));
+        Ok(selectors)
     }
 
     fn parse_simple_selector(&mut self) -> SimpleSelector {
+        let start = self.parser.location();
         let mut selector = SimpleSelector {
             tag_name: None,
             id: None,
             class: Vec::new(),
+            span: Default::default(),
         };
-        while !self.parser.ended() {
-            match self.parser.next_char() {
+        while let Some(next) = self.parser.next_char() {
+            match next {
                 '#' => {
                     self.parser.consume_char();
                     selector.id = Some(self.parse_identifier());
@@ -85,35 +182,196 @@ impl CSSParser {
                 _ => break,
             }
         }
+        selector.span = self.parser.span_from(start);
         selector
     }
 
-    fn parse_declarations(&mut self) -> Vec<Declaration> {
-        assert_eq!(self.parser.consume_char(), '{');
+    /// Parses declarations up to the `}` that closes a block, or to the end
+    /// of the input if they aren't `in_block`.
+    fn parse_declaration_list(&mut self, in_block: bool) -> Vec<Declaration> {
         let mut declarations = Vec::new();
         loop {
-            self.parser.consume_whitespace();
-            if self.parser.next_char() == '}' {
-                self.parser.consume_char();
-                break;
+            self.consume_whitespace();
+            match self.parser.next_char() {
+                Some('}') if in_block => {
+                    self.parser.consume_char();
+                    break;
+                }
+                Some('}') => {
+                    self.errors.push(self.parser.unexpected("declaration"));
+                    self.parser.consume_char();
+                }
+                Some(';') => {
+                    self.parser.consume_char();
+                }
+                Some(_) => match self.parse_declaration() {
+                    Ok(declaration) => declarations.push(declaration),
+                    Err(error) => {
+                        self.errors.push(error);
+                        self.skip_declaration();
+                    }
+                },
+                None => {
+                    if in_block {
+                        self.errors.push(self.parser.unexpected("'}'"));
+                    }
+                    break;
+                }
             }
-            declarations.push(self.parse_declaration());
         }
         declarations
     }
 
-    fn parse_declaration(&mut self) -> Declaration {
+    fn parse_declaration(&mut self) -> ParseResult<Declaration> {
+        let start = self.parser.location();
         let property_name = self.parse_identifier();
-        self.parser.consume_whitespace();
-        assert_eq!(self.parser.consume_char(), ':');
-        self.parser.consume_whitespace();
-        let value = self.parse_value();
-        self.parser.consume_whitespace();
-        assert_eq!(self.parser.consume_char(), ';');
-
-        Declaration {
+        if property_name.is_empty() {
+            return Err(self.parser.unexpected("property name or '}'"));
+        }
+        self.consume_whitespace();
+        self.parser.expect_char(':')?;
+        self.consume_whitespace();
+        let value = self.parse_value()?;
+        self.consume_whitespace();
+        let important = self.parse_important()?;
+        match self.parser.next_char() {
+            Some(';') => {
+                self.parser.consume_char();
+            }
+            // The last declaration in a block doesn't need a semicolon.
+            Some('}') | None => {}
+            Some(_) => return Err(self.parser.unexpected("';'")),
+        }
+
+        Ok(Declaration {
             name: property_name,
             value,
+            important,
+            span: self.parser.span_from(start),
+        })
+    }
+
+    fn parse_important(&mut self) -> ParseResult<bool> {
+        if self.parser.next_char() != Some('!') {
+            return Ok(false);
+        }
+        self.parser.consume_char();
+        self.consume_whitespace();
+        if !self.parse_identifier().eq_ignore_ascii_case("important") {
+            return Err(self.parser.unexpected("'important'"));
+        }
+        self.consume_whitespace();
+        Ok(true)
+    }
+
+    /// Skips whitespace and `/* ... */` comments, which may appear anywhere
+    /// whitespace is allowed. An unterminated comment runs to the end of the
+    /// input.
+    fn consume_whitespace(&mut self) {
+        loop {
+            self.parser.consume_whitespace();
+            if !self.skip_comment() {
+                break;
+            }
+        }
+    }
+
+    fn skip_comment(&mut self) -> bool {
+        if !self.parser.starts_with("/*") {
+            return false;
+        }
+        self.parser.consume_char();
+        self.parser.consume_char();
+        while !self.parser.ended() && !self.parser.starts_with("*/") {
+            self.parser.consume_char();
+        }
+        self.parser.consume_char();
+        self.parser.consume_char();
+        true
+    }
+
+    /// Drops the rest of an invalid declaration, stopping after the next
+    /// `;` or before the `}` that closes the enclosing block.
+    fn skip_declaration(&mut self) {
+        while let Some(next) = self.parser.next_char() {
+            match next {
+                ';' => {
+                    self.parser.consume_char();
+                    break;
+                }
+                '}' => break,
+                _ => self.skip_component_value(),
+            }
+        }
+    }
+
+    /// Drops a rule whose prelude couldn't be parsed, along with its block.
+    fn skip_qualified_rule(&mut self) {
+        while let Some(next) = self.parser.next_char() {
+            self.skip_component_value();
+            if next == '{' {
+                break;
+            }
+        }
+    }
+
+    /// Drops an at-rule, which ends either at a `;` or after its block.
+    fn skip_at_rule(&mut self) {
+        let start = self.parser.location();
+        self.parser.consume_char();
+        let name = self.parse_identifier();
+        self.errors.push(ParseError::UnsupportedAtRule {
+            name,
+            span: self.parser.span_from(start),
+        });
+        while let Some(next) = self.parser.next_char() {
+            if next == ';' {
+                self.parser.consume_char();
+                break;
+            }
+            self.skip_component_value();
+            if next == '{' {
+                break;
+            }
+        }
+    }
+
+    /// Consumes a single character, or a whole string or bracketed block
+    /// including anything nested inside it. An unclosed block runs to the end
+    /// of the input.
+    fn skip_component_value(&mut self) {
+        if self.skip_comment() {
+            return;
+        }
+        let closing = match self.parser.consume_char() {
+            Some('{') => '}',
+            Some('(') => ')',
+            Some('[') => ']',
+            Some(quote @ ('"' | '\'')) => {
+                self.skip_string(quote);
+                return;
+            }
+            _ => return,
+        };
+        while let Some(next) = self.parser.next_char() {
+            if next == closing {
+                self.parser.consume_char();
+                break;
+            }
+            self.skip_component_value();
+        }
+    }
+
+    fn skip_string(&mut self, quote: char) {
+        while let Some(next) = self.parser.consume_char() {
+            match next {
+                '\\' => {
+                    self.parser.consume_char();
+                }
+                '\n' => break,
+                c if c == quote => break,
+                _ => {}
+            }
         }
     }
 
@@ -121,48 +379,215 @@ impl CSSParser {
         self.parser.consume_while(valid_identifier_char)
     }
 
-    fn parse_value(&mut self) -> Value {
+    fn parse_value(&mut self) -> ParseResult<Value> {
         match self.parser.next_char() {
-            '0'..='9' => self.parse_length(),
-            '#' => self.parse_color(),
-            _ => Value::Keyword(self.parse_identifier()),
+            Some('0'..='9') => self.parse_length(),
+            Some('#') => self.parse_color(),
+            Some(c) if valid_identifier_char(c) => Ok(Value::Keyword(self.parse_identifier())),
+            _ => Err(self.parser.unexpected("value")),
         }
     }
 
-    fn parse_length(&mut self) -> Value {
-        Value::Length(self.parse_float(), self.parse_unit())
+    fn parse_length(&mut self) -> ParseResult<Value> {
+        Ok(Value::Length(self.parse_float()?, self.parse_unit()?))
     }
 
-    fn parse_float(&mut self) -> f32 {
+    fn parse_float(&mut self) -> ParseResult<f32> {
+        let start = self.parser.location();
         let s = self.parser.consume_while(|c| matches!(c, '0'..='9' | '.'));
-        s.parse().unwrap()
-    }
-
-    fn parse_unit(&mut self) -> Unit {
-        match &*self.parse_identifier().to_ascii_lowercase() {
-            "px" => Unit::Px,
-            _ => panic!("unrecognized unit"),
-        }
-    }
-		
-		fn parse_color(&mut self) -> Value {
-			assert_eq!(self.parser.consume_char(), '#');
-			Value::ColorValue(Color {
-					r: self.parse_hex_pair(),
-					g: self.parse_hex_pair(),
-					b: self.parse_hex_pair(),
-					a: 255 
-			})
-		}
-
-		fn parse_hex_pair(&mut self) -> u8 {
-				let next_pair = self.parser.next_chars(2);
-				self.parser.consume_char();
-				self.parser.consume_char();
-				u8::from_str_radix(&next_pair, 16).unwrap()
-		}
+        s.parse().map_err(|_| ParseError::InvalidNumber {
+            value: s,
+            span: self.parser.span_from(start),
+        })
+    }
+
+    fn parse_unit(&mut self) -> ParseResult<Unit> {
+        let start = self.parser.location();
+        let unit = self.parse_identifier();
+        match &*unit.to_ascii_lowercase() {
+            "px" => Ok(Unit::Px),
+            _ => Err(ParseError::UnrecognizedUnit {
+                unit,
+                span: self.parser.span_from(start),
+            }),
+        }
+    }
+
+    fn parse_color(&mut self) -> ParseResult<Value> {
+        self.parser.expect_char('#')?;
+        Ok(Value::ColorValue(Color {
+            r: self.parse_hex_pair()?,
+            g: self.parse_hex_pair()?,
+            b: self.parse_hex_pair()?,
+            a: 255,
+        }))
+    }
+
+    fn parse_hex_pair(&mut self) -> ParseResult<u8> {
+        let start = self.parser.location();
+        let next_pair = self.parser.next_chars(2);
+        // Nothing is consumed unless it's valid, so recovery starts from
+        // whatever ended the color, which may be the `;` before the next
+        // declaration.
+        if next_pair.len() != 2 || !next_pair.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(ParseError::InvalidColor { value: next_pair, span: self.parser.span_from(start) });
+        }
+        self.parser.consume_char();
+        self.parser.consume_char();
+        Ok(u8::from_str_radix(&next_pair, 16).unwrap_or_default())
+    }
 }
 
 fn valid_identifier_char(c: char) -> bool {
 	matches!(c, 'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_')
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Strings and escapes aren't values or selectors the parser supports,
+    /// so the declarations and rule using them are dropped, but a `;`, `}`
+    /// or quote inside them mustn't end anything early.
+    const SOURCE: &str = r#"
+        h1, .title.big , #main{ margin : 10px ; color:#FF0080 !important }
+        * { display: none ! important; }
+        p {}
+        a { content: "a;}b"; font-family: 'x\'y'; color: #00ff00 }
+        .a\:b { width: 1px }
+        div { background: url("\"}"); padding: 2px; }
+    "#;
+
+    /// Prints `stylesheet` both ways, checking that parsing either one
+    /// gives back a stylesheet that prints the same.
+    fn round_trip(stylesheet: &StyleSheet) -> (String, String) {
+        let (pretty, minified) = (stylesheet.to_string(), stylesheet.minified());
+        for css in [&pretty, &minified] {
+            let (reparsed, errors) = parse_with_errors(css.clone(), None);
+            assert_eq!(errors, Vec::new(), "{}", css);
+            assert_eq!(reparsed.to_string(), pretty);
+            assert_eq!(reparsed.minified(), minified);
+        }
+        (pretty, minified)
+    }
+
+    /// The minified stylesheet, and the number of errors parsing `source`.
+    fn recovered(source: &str) -> (String, usize) {
+        let (stylesheet, errors) = parse_with_errors(source.to_string(), None);
+        (stylesheet.minified(), errors.len())
+    }
+
+    #[test]
+    fn invalid_declarations_are_skipped() {
+        assert_eq!(
+            recovered("a { colour; color: red; margin: 5 px; 5px; padding: 1px }"),
+            ("a{color:red;padding:1px}".to_string(), 3)
+        );
+    }
+
+    #[test]
+    fn rules_with_invalid_selectors_are_skipped() {
+        assert_eq!(
+            recovered("a > b { color: red; } , p { color: red } p { margin: 1px }"),
+            ("p{margin:1px}".to_string(), 2)
+        );
+    }
+
+    #[test]
+    fn unsupported_at_rules_are_skipped() {
+        let (stylesheet, errors) = parse_with_errors(
+            "@media screen { a { color: red } } @import 'x.css'; p { margin: 1px }".to_string(),
+            None,
+        );
+        assert_eq!(stylesheet.minified(), "p{margin:1px}");
+        let names: Vec<&str> = errors
+            .iter()
+            .map(|error| match error {
+                ParseError::UnsupportedAtRule { name, .. } => name.as_str(),
+                _ => panic!("unexpected error {:?}", error),
+            })
+            .collect();
+        assert_eq!(names, ["media", "import"]);
+    }
+
+    #[test]
+    fn brackets_and_strings_are_skipped_whole() {
+        assert_eq!(
+            recovered(r#"a { background: url("};") [ { ; } ] (;) 'it\'s;}'; color: red }"#),
+            ("a{color:red}".to_string(), 1)
+        );
+        assert_eq!(
+            recovered(r#"a[title="}"] { color: red } @font-face { src: url('{') } p { margin: 1px }"#),
+            ("p{margin:1px}".to_string(), 2)
+        );
+    }
+
+    #[test]
+    fn blocks_left_open_are_closed_at_eof() {
+        assert_eq!(recovered("a { color: red; margin: 1px"), ("a{color:red;margin:1px}".to_string(), 1));
+        assert_eq!(recovered("p { margin: 1px } a > { color: red"), ("p{margin:1px}".to_string(), 1));
+        assert_eq!(recovered("p { margin: 1px } @media { a { color: red }"), ("p{margin:1px}".to_string(), 1));
+    }
+
+    #[test]
+    fn descendant_selectors() {
+        let source = "p, div  .a\n#b p { margin: 1px }";
+        let (stylesheet, errors) = parse_with_errors(source.to_string(), None);
+        assert!(errors.is_empty());
+        let selectors = &stylesheet.rules[0].selectors;
+        assert_eq!(selectors[0].specificity(), (1, 1, 2));
+        assert_eq!(&source[selectors[0].span().byte_range()], "div  .a\n#b p");
+        assert_eq!(stylesheet.minified(), "div .a #b p,p{margin:1px}");
+    }
+
+    #[test]
+    fn comments_are_skipped_like_whitespace() {
+        assert_eq!(
+            recovered(concat!(
+                "/* a */ a/* b */{ color/* c */:/* d */red /* e */ !/* f */important/* g */; }",
+                " /* h */ p { margin: 1px }/* i */",
+            )),
+            ("a{color:red!important}p{margin:1px}".to_string(), 0)
+        );
+    }
+
+    #[test]
+    fn unterminated_comments_run_to_eof() {
+        assert_eq!(recovered("p { margin: 1px } /* a { color: red }"), ("p{margin:1px}".to_string(), 0));
+        assert_eq!(recovered("p { margin: 1px /* }"), ("p{margin:1px}".to_string(), 1));
+        assert_eq!(recovered("p { margin: 1px } /"), ("p{margin:1px}".to_string(), 1));
+    }
+
+    #[test]
+    fn declarations_after_invalid_colors_are_kept() {
+        for color in ["#fff", "#zz", "#12345z"] {
+            let source = format!("a {{ color: {}; margin: 10px; }}", color);
+            let (stylesheet, errors) = parse_with_errors(source, None);
+            assert!(matches!(errors[..], [ParseError::InvalidColor { .. }]), "{}: {:?}", color, errors);
+            assert_eq!(stylesheet.minified(), "a{margin:10px}", "{}", color);
+        }
+    }
+
+    #[test]
+    fn pretty_printing_round_trips() {
+        let (pretty, _) = round_trip(&parse(SOURCE.to_string()));
+        assert_eq!(
+            pretty,
+            "#main, .title.big, h1 {\n    margin: 10px;\n    color: #ff0080 !important;\n}\n\n\
+             * {\n    display: none !important;\n}\n\n\
+             p {}\n\n\
+             a {\n    color: #00ff00;\n}\n\n\
+             div {\n    padding: 2px;\n}"
+        );
+    }
+
+    #[test]
+    fn minified_printing_round_trips() {
+        let (_, minified) = round_trip(&parse(SOURCE.to_string()));
+        assert_eq!(
+            minified,
+            "#main,.title.big,h1{margin:10px;color:#ff0080!important}\
+             *{display:none!important}p{}a{color:#00ff00}div{padding:2px}"
+        );
+    }
+}
@@ -1,3 +1,7 @@
+use std::fmt::{self, Write};
+
+use crate::parse::span::Span;
+
 #[derive(Debug)]
 pub struct StyleSheet {
     pub rules: Vec<Rule>,
@@ -7,11 +11,19 @@ pub struct StyleSheet {
 pub struct Rule {
     pub selectors: Vec<Selector>,
     pub declarations: Vec<Declaration>,
+    pub span: Span,
 }
 
 #[derive(Debug)]
 pub enum Selector {
     Simple(SimpleSelector),
+    /// Matches elements that `subject` matches and that are somewhere under
+    /// an element `ancestor` matches, like `div p`.
+    Descendant {
+        ancestor: Box<Selector>,
+        subject: SimpleSelector,
+        span: Span,
+    },
 }
 
 #[derive(Debug)]
@@ -19,12 +31,16 @@ pub struct SimpleSelector {
     pub tag_name: Option<String>,
     pub id: Option<String>,
     pub class: Vec<String>,
+    pub span: Span,
 }
 
 #[derive(Debug)]
 pub struct Declaration {
     pub name: String,
     pub value: Value,
+    /// Whether the declaration ended with `!important`.
+    pub important: bool,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -51,10 +67,170 @@ pub type Specificity = (usize, usize, usize);
 
 impl Selector {
     pub fn specificity(&self) -> Specificity {
-        let Selector::Simple(ref simple) = *self;
-        let ids = simple.id.iter().count();
-        let classes = simple.class.len();
-        let tag_names = simple.tag_name.iter().count();
+        match *self {
+            Selector::Simple(ref simple) => simple.specificity(),
+            Selector::Descendant { ref ancestor, ref subject, .. } => {
+                let (ids, classes, tag_names) = ancestor.specificity();
+                let (subject_ids, subject_classes, subject_tag_names) = subject.specificity();
+                (ids + subject_ids, classes + subject_classes, tag_names + subject_tag_names)
+            }
+        }
+    }
+
+    pub fn span(&self) -> &Span {
+        match *self {
+            Selector::Simple(ref simple) => &simple.span,
+            Selector::Descendant { ref span, .. } => span,
+        }
+    }
+}
+
+impl SimpleSelector {
+    fn specificity(&self) -> Specificity {
+        let ids = self.id.iter().count();
+        let classes = self.class.len();
+        let tag_names = self.tag_name.iter().count();
         (ids, classes, tag_names)
     }
 }
+
+impl StyleSheet {
+    /// Serializes the stylesheet with no more whitespace than it needs.
+    pub fn minified(&self) -> String {
+        let mut css = String::new();
+        self.write_css(&mut css, true).expect("writing to a String can't fail");
+        css
+    }
+
+    fn write_css(&self, out: &mut dyn Write, minified: bool) -> fmt::Result {
+        for (i, rule) in self.rules.iter().enumerate() {
+            if i > 0 && !minified {
+                out.write_str("\n\n")?;
+            }
+            rule.write_css(out, minified)?;
+        }
+        Ok(())
+    }
+}
+
+/// Prints the stylesheet as canonical CSS, one declaration per line.
+impl fmt::Display for StyleSheet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_css(f, false)
+    }
+}
+
+impl Rule {
+    fn write_css(&self, out: &mut dyn Write, minified: bool) -> fmt::Result {
+        for (i, selector) in self.selectors.iter().enumerate() {
+            if i > 0 {
+                out.write_str(if minified { "," } else { ", " })?;
+            }
+            write!(out, "{}", selector)?;
+        }
+        if minified {
+            out.write_char('{')?;
+            for (i, declaration) in self.declarations.iter().enumerate() {
+                if i > 0 {
+                    out.write_char(';')?;
+                }
+                declaration.write_css(out, true)?;
+            }
+            return out.write_char('}');
+        }
+        if self.declarations.is_empty() {
+            return out.write_str(" {}");
+        }
+        out.write_str(" {\n")?;
+        for declaration in &self.declarations {
+            out.write_str("    ")?;
+            declaration.write_css(out, false)?;
+            out.write_str(";\n")?;
+        }
+        out.write_char('}')
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_css(f, false)
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Selector::Simple(ref simple) => write!(f, "{}", simple),
+            Selector::Descendant { ref ancestor, ref subject, .. } => write!(f, "{} {}", ancestor, subject),
+        }
+    }
+}
+
+impl fmt::Display for SimpleSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.tag_name.is_none() && self.id.is_none() && self.class.is_empty() {
+            return f.write_char('*');
+        }
+        if let Some(ref tag_name) = self.tag_name {
+            f.write_str(tag_name)?;
+        }
+        if let Some(ref id) = self.id {
+            write!(f, "#{}", id)?;
+        }
+        for class in &self.class {
+            write!(f, ".{}", class)?;
+        }
+        Ok(())
+    }
+}
+
+impl Declaration {
+    fn write_css(&self, out: &mut dyn Write, minified: bool) -> fmt::Result {
+        match minified {
+            true => write!(out, "{}:{}", self.name, self.value)?,
+            false => write!(out, "{}: {}", self.name, self.value)?,
+        }
+        match (self.important, minified) {
+            (true, true) => out.write_str("!important"),
+            (true, false) => out.write_str(" !important"),
+            (false, _) => Ok(()),
+        }
+    }
+}
+
+/// Prints the declaration without the `;` that ends it.
+impl fmt::Display for Declaration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_css(f, false)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Keyword(ref keyword) => f.write_str(keyword),
+            Value::Length(length, ref unit) => write!(f, "{}{}", length, unit),
+            Value::ColorValue(ref color) => write!(f, "{}", color),
+        }
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Unit::Px => f.write_str("px"),
+        }
+    }
+}
+
+/// Prints opaque colors as `#rrggbb` and translucent ones as `rgb()` with
+/// an alpha component.
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.a == 255 {
+            return write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b);
+        }
+        let alpha = (self.a as f32 / 255.0 * 1000.0).round() / 1000.0;
+        write!(f, "rgb({} {} {} / {})", self.r, self.g, self.b, alpha)
+    }
+}
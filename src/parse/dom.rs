@@ -1,32 +1,126 @@
-use std::collections::{HashMap, HashSet};
+use std::{collections::HashSet, mem, ops::Index, sync::mpsc::Sender};
 
-#[derive(Debug)]
+use crate::parse::{events::Listeners, mutation::MutationRecord, span::Span};
+
+#[derive(Debug, Clone)]
 pub struct Node {
 	pub children: Vec<Node>,
 	pub node_type: NodeType,
+	pub span: Span,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum NodeType {
+	Document(QuirksMode),
+	Doctype(DoctypeData),
 	Element(ElementData),
 	Text(String),
 	Comment(String),
 }
 
-pub type AttrMap = HashMap<String, String>;
+/// How closely a document asked to follow the standards, decided by its
+/// DOCTYPE. Documents without one are rendered in quirks mode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QuirksMode {
+	#[default]
+	NoQuirks,
+	LimitedQuirks,
+	Quirks,
+}
+
+#[derive(Debug, Clone)]
+pub struct DoctypeData {
+	pub name: String,
+	pub public_id: String,
+	pub system_id: String,
+}
 
-#[derive(Debug)]
+/// An element's attributes, in the order they appeared in the source.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AttrMap {
+	attributes: Vec<(String, String)>,
+}
+
+impl AttrMap {
+	pub fn new() -> AttrMap {
+		AttrMap::default()
+	}
+
+	pub fn get(&self, name: &str) -> Option<&String> {
+		self.attributes.iter().find(|(key, _)| key == name).map(|(_, value)| value)
+	}
+
+	pub fn contains_key(&self, name: &str) -> bool {
+		self.get(name).is_some()
+	}
+
+	/// Sets an attribute, keeping its original position if it was already
+	/// present, and returns the old value.
+	pub fn insert(&mut self, name: String, value: String) -> Option<String> {
+		match self.attributes.iter_mut().find(|(key, _)| *key == name) {
+			Some((_, existing)) => Some(std::mem::replace(existing, value)),
+			None => {
+				self.attributes.push((name, value));
+				None
+			}
+		}
+	}
+
+	pub fn remove(&mut self, name: &str) -> Option<String> {
+		let position = self.attributes.iter().position(|(key, _)| key == name)?;
+		Some(self.attributes.remove(position).1)
+	}
+
+	pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+		self.attributes.iter().map(|(name, value)| (name, value))
+	}
+
+	pub fn len(&self) -> usize {
+		self.attributes.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.attributes.is_empty()
+	}
+}
+
+/// Collecting keeps the first of any duplicate attributes, like HTML does.
+impl FromIterator<(String, String)> for AttrMap {
+	fn from_iter<I: IntoIterator<Item = (String, String)>>(iter: I) -> AttrMap {
+		let mut attributes = AttrMap::new();
+		for (name, value) in iter {
+			if !attributes.contains_key(&name) {
+				attributes.attributes.push((name, value));
+			}
+		}
+		attributes
+	}
+}
+
+#[derive(Debug, Clone)]
 pub struct ElementData {
 	pub tag_name: String,
 	pub attributes: AttrMap,
 }
 
+pub fn document(quirks_mode: QuirksMode, children: Vec<Node>) -> Node {
+	Node { children, node_type: NodeType::Document(quirks_mode), span: Span::default() }
+}
+
+pub fn doctype(name: String, public_id: String, system_id: String) -> Node {
+	Node {
+		children: Vec::new(),
+		node_type: NodeType::Doctype(DoctypeData { name, public_id, system_id }),
+		span: Span::default(),
+	}
+}
+
 pub fn text(content: String) -> Node {
-	Node { children: Vec::new(), node_type: NodeType::Text(content) }
+	Node { children: Vec::new(), node_type: NodeType::Text(content), span: Span::default() }
 }
 
 pub fn comment(content: String) -> Node {
-	Node { children: Vec::new(), node_type: NodeType::Comment(content) }
+	Node { children: Vec::new(), node_type: NodeType::Comment(content), span: Span::default() }
 }
 
 pub fn element(tag_name: String, attributes: AttrMap, children: Vec<Node>) -> Node {
@@ -35,7 +129,60 @@ pub fn element(tag_name: String, attributes: AttrMap, children: Vec<Node>) -> No
 		node_type: NodeType::Element(ElementData {
 			tag_name,
 			attributes,	
-		})
+		}),
+		span: Span::default(),
+	}
+}
+
+impl Node {
+	/// Moves the node's fields out, which its `Drop` doesn't otherwise
+	/// allow.
+	fn into_parts(mut self) -> (NodeType, Span, Vec<Node>) {
+		let node_type = mem::replace(&mut self.node_type, NodeType::Text(String::new()));
+		(node_type, mem::take(&mut self.span), mem::take(&mut self.children))
+	}
+
+	pub fn with_span(mut self, span: Span) -> Node {
+		self.span = span;
+		self
+	}
+
+	/// The text of this node and all its descendants, in document order.
+	pub fn text_content(&self) -> String {
+		match self.node_type {
+			NodeType::Text(ref text) => text.clone(),
+			NodeType::Comment(_) | NodeType::Doctype(_) => String::new(),
+			_ => {
+				let mut text = String::new();
+				let mut unvisited: Vec<&Node> = self.children.iter().rev().collect();
+				while let Some(node) = unvisited.pop() {
+					match node.node_type {
+						NodeType::Text(ref content) => text.push_str(content),
+						_ => unvisited.extend(node.children.iter().rev()),
+					}
+				}
+				text
+			}
+		}
+	}
+
+	/// The document's quirks mode, or `NoQuirks` if this isn't a document.
+	pub fn quirks_mode(&self) -> QuirksMode {
+		match self.node_type {
+			NodeType::Document(quirks_mode) => quirks_mode,
+			_ => QuirksMode::NoQuirks,
+		}
+	}
+}
+
+/// Drops the tree a node at a time rather than recursively, so a deeply
+/// nested document can't overflow the stack.
+impl Drop for Node {
+	fn drop(&mut self) {
+		let mut nodes = mem::take(&mut self.children);
+		while let Some(mut node) = nodes.pop() {
+			nodes.append(&mut node.children);
+		}
 	}
 }
 
@@ -51,3 +198,257 @@ impl ElementData {
 			}
 		}
 }
+
+/// Identifies a node in a `Document`. Ids stay valid for as long as the
+/// document does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(usize);
+
+/// A DOM whose nodes live in an arena and link to their parent, siblings
+/// and first and last children, so the tree can be walked in any direction.
+///
+/// Trees built with `element`, `text` and the other constructors, or
+/// returned by `html::parse`, convert into one with `Document::from`, or
+/// can be added to one with `create_node`.
+#[derive(Debug)]
+pub struct Document {
+	nodes: Vec<NodeData>,
+	pub(crate) observers: Vec<Sender<MutationRecord>>,
+	pub(crate) listeners: Listeners,
+}
+
+/// A clone has the same nodes, but none of the original's observers or
+/// event listeners.
+impl Clone for Document {
+	fn clone(&self) -> Document {
+		Document { nodes: self.nodes.clone(), observers: Vec::new(), listeners: Listeners::default() }
+	}
+}
+
+/// A node in a `Document`.
+#[derive(Debug, Clone)]
+pub struct NodeData {
+	pub node_type: NodeType,
+	pub span: Span,
+	parent: Option<NodeId>,
+	previous_sibling: Option<NodeId>,
+	next_sibling: Option<NodeId>,
+	first_child: Option<NodeId>,
+	last_child: Option<NodeId>,
+}
+
+impl NodeData {
+	fn new(node_type: NodeType, span: Span) -> NodeData {
+		NodeData {
+			node_type,
+			span,
+			parent: None,
+			previous_sibling: None,
+			next_sibling: None,
+			first_child: None,
+			last_child: None,
+		}
+	}
+
+	pub fn parent(&self) -> Option<NodeId> {
+		self.parent
+	}
+
+	pub fn previous_sibling(&self) -> Option<NodeId> {
+		self.previous_sibling
+	}
+
+	pub fn next_sibling(&self) -> Option<NodeId> {
+		self.next_sibling
+	}
+
+	pub fn first_child(&self) -> Option<NodeId> {
+		self.first_child
+	}
+
+	pub fn last_child(&self) -> Option<NodeId> {
+		self.last_child
+	}
+
+	pub fn element(&self) -> Option<&ElementData> {
+		match self.node_type {
+			NodeType::Element(ref element) => Some(element),
+			_ => None,
+		}
+	}
+}
+
+impl Document {
+	/// An empty document, containing only its root `Document` node.
+	pub fn new(quirks_mode: QuirksMode) -> Document {
+		Document {
+			nodes: vec![NodeData::new(NodeType::Document(quirks_mode), Span::default())],
+			observers: Vec::new(),
+			listeners: Listeners::default(),
+		}
+	}
+
+	/// The `Document` node every other node descends from.
+	pub fn root(&self) -> NodeId {
+		NodeId(0)
+	}
+
+	pub fn quirks_mode(&self) -> QuirksMode {
+		match self[self.root()].node_type {
+			NodeType::Document(quirks_mode) => quirks_mode,
+			_ => QuirksMode::NoQuirks,
+		}
+	}
+
+	pub fn node(&self, id: NodeId) -> &NodeData {
+		&self.nodes[id.0]
+	}
+
+	/// The node's type and span can be changed through this, but not its
+	/// place in the tree. Only for the methods that report their changes to
+	/// observers, like `set_attribute`.
+	pub(crate) fn node_mut(&mut self, id: NodeId) -> &mut NodeData {
+		&mut self.nodes[id.0]
+	}
+
+	/// The node's element, if it is one.
+	pub fn element(&self, id: NodeId) -> Option<&ElementData> {
+		self[id].element()
+	}
+
+	/// Adds `node` and all its descendants to the document without putting
+	/// them in the tree, returning the id `node` was given. It can then be
+	/// inserted with `append_child` or `insert_before`.
+	pub fn create_node(&mut self, node: Node) -> NodeId {
+		let (node_type, span, children) = node.into_parts();
+		let id = self.push_node(node_type, span);
+		let mut unvisited = vec![(id, children.into_iter())];
+		while let Some((parent, children)) = unvisited.last_mut() {
+			let parent = *parent;
+			match children.next() {
+				Some(child) => {
+					let (node_type, span, children) = child.into_parts();
+					let child_id = self.push_node(node_type, span);
+					self.link(parent, child_id, None);
+					unvisited.push((child_id, children.into_iter()));
+				}
+				None => {
+					unvisited.pop();
+				}
+			}
+		}
+		id
+	}
+
+	fn push_node(&mut self, node_type: NodeType, span: Span) -> NodeId {
+		let id = NodeId(self.nodes.len());
+		self.nodes.push(NodeData::new(node_type, span));
+		id
+	}
+
+	/// Makes detached `child` the child of `parent` before `before`, or its
+	/// last child if that's `None`.
+	pub(crate) fn link(&mut self, parent: NodeId, child: NodeId, before: Option<NodeId>) {
+		let previous_sibling = match before {
+			Some(before) => self[before].previous_sibling,
+			None => self[parent].last_child,
+		};
+		match previous_sibling {
+			Some(previous_sibling) => self.nodes[previous_sibling.0].next_sibling = Some(child),
+			None => self.nodes[parent.0].first_child = Some(child),
+		}
+		match before {
+			Some(before) => self.nodes[before.0].previous_sibling = Some(child),
+			None => self.nodes[parent.0].last_child = Some(child),
+		}
+		let node = &mut self.nodes[child.0];
+		node.parent = Some(parent);
+		node.previous_sibling = previous_sibling;
+		node.next_sibling = before;
+	}
+
+	/// Takes `id` out of its parent's children, leaving it detached.
+	pub(crate) fn unlink(&mut self, id: NodeId) {
+		let NodeData { parent, previous_sibling, next_sibling, .. } = self.nodes[id.0];
+		let Some(parent) = parent else {
+			return;
+		};
+		match previous_sibling {
+			Some(previous_sibling) => self.nodes[previous_sibling.0].next_sibling = next_sibling,
+			None => self.nodes[parent.0].first_child = next_sibling,
+		}
+		match next_sibling {
+			Some(next_sibling) => self.nodes[next_sibling.0].previous_sibling = previous_sibling,
+			None => self.nodes[parent.0].last_child = previous_sibling,
+		}
+		let node = &mut self.nodes[id.0];
+		node.parent = None;
+		node.previous_sibling = None;
+		node.next_sibling = None;
+	}
+
+	/// A copy of the subtree rooted at `id`, as an owned `Node`.
+	pub fn to_node(&self, id: NodeId) -> Node {
+		let copy = |id: NodeId| Node {
+			children: Vec::new(),
+			node_type: self[id].node_type.clone(),
+			span: self[id].span.clone(),
+		};
+		// Each node is copied after its children, which are the last
+		// copies made.
+		let mut copies: Vec<Node> = Vec::new();
+		for descendant in self.descendants_post_order(id).chain([id]) {
+			let mut node = copy(descendant);
+			node.children = copies.split_off(copies.len() - self.children(descendant).count());
+			copies.push(node);
+		}
+		copies.pop().expect("the root is always copied")
+	}
+
+	/// The text of this node and all its descendants, in document order.
+	pub fn text_content(&self, id: NodeId) -> String {
+		match self[id].node_type {
+			NodeType::Text(ref text) => text.clone(),
+			NodeType::Comment(_) | NodeType::Doctype(_) => String::new(),
+			_ => self
+				.descendants(id)
+				.filter_map(|descendant| match self[descendant].node_type {
+					NodeType::Text(ref text) => Some(text.as_str()),
+					_ => None,
+				})
+				.collect(),
+		}
+	}
+}
+
+/// A `Document` node becomes the root of the new document. Any other node
+/// becomes the only child of an empty one.
+impl From<Node> for Document {
+	fn from(node: Node) -> Document {
+		let mut document = Document::new(node.quirks_mode());
+		let root = document.root();
+		match node.node_type {
+			NodeType::Document(_) => {
+				let (_, span, children) = node.into_parts();
+				document.nodes[root.0].span = span;
+				for child in children {
+					let child = document.create_node(child);
+					document.link(root, child, None);
+				}
+			}
+			_ => {
+				let node = document.create_node(node);
+				document.link(root, node, None);
+			}
+		}
+		document
+	}
+}
+
+impl Index<NodeId> for Document {
+	type Output = NodeData;
+
+	fn index(&self, id: NodeId) -> &NodeData {
+		self.node(id)
+	}
+}
@@ -0,0 +1,458 @@
+/// The encodings HTML documents can be decoded from. ISO-8859-1 and ASCII
+/// are decoded as windows-1252, which is what browsers do with them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+	Utf8,
+	Utf16Le,
+	Utf16Be,
+	Windows1252,
+}
+
+impl Encoding {
+	/// Looks up an encoding by one of the labels the Encoding Standard
+	/// gives it, such as `latin1` or `utf8`.
+	pub fn for_label(label: &str) -> Option<Encoding> {
+		let label = label.trim_matches(|c: char| c.is_ascii_whitespace()).to_ascii_lowercase();
+		match label.as_str() {
+			"unicode-1-1-utf-8" | "unicode11utf8" | "unicode20utf8" | "utf-8" | "utf8" | "x-unicode20utf8" => {
+				Some(Encoding::Utf8)
+			}
+			"csunicode" | "iso-10646-ucs-2" | "ucs-2" | "unicode" | "unicodefeff" | "utf-16" | "utf-16le" => {
+				Some(Encoding::Utf16Le)
+			}
+			"unicodefffe" | "utf-16be" => Some(Encoding::Utf16Be),
+			"ansi_x3.4-1968" | "ascii" | "cp1252" | "cp819" | "csisolatin1" | "ibm819" | "iso-8859-1"
+			| "iso-ir-100" | "iso8859-1" | "iso88591" | "iso_8859-1" | "iso_8859-1:1987" | "l1" | "latin1"
+			| "us-ascii" | "windows-1252" | "x-cp1252" => Some(Encoding::Windows1252),
+			_ => None,
+		}
+	}
+
+	pub fn name(self) -> &'static str {
+		match self {
+			Encoding::Utf8 => "UTF-8",
+			Encoding::Utf16Le => "UTF-16LE",
+			Encoding::Utf16Be => "UTF-16BE",
+			Encoding::Windows1252 => "windows-1252",
+		}
+	}
+}
+
+/// Decides how to decode an HTML document. A byte order mark wins, then
+/// the caller's `hint` (from a `Content-Type` header, say), then a
+/// `<meta charset>` or `<meta http-equiv>` in the first 1024 bytes. Without
+/// any of those the document is taken to be UTF-8 if it's valid UTF-8, and
+/// windows-1252 otherwise.
+pub fn sniff(bytes: &[u8], hint: Option<Encoding>) -> Encoding {
+	if let Some((encoding, _)) = byte_order_mark(bytes) {
+		return encoding;
+	}
+	if let Some(encoding) = hint.or_else(|| prescan(bytes)) {
+		return encoding;
+	}
+	match std::str::from_utf8(bytes) {
+		Ok(_) => Encoding::Utf8,
+		Err(_) => Encoding::Windows1252,
+	}
+}
+
+/// Like `sniff`, for the start of a document whose remaining bytes haven't
+/// arrived yet, so the last character may be incomplete.
+pub fn sniff_start(bytes: &[u8], hint: Option<Encoding>) -> Encoding {
+	sniff(&bytes[..complete_prefix_len(bytes, Encoding::Utf8)], hint)
+}
+
+/// Decodes `bytes`, dropping a leading byte order mark for `encoding`.
+/// Invalid sequences become U+FFFD.
+pub fn decode(bytes: &[u8], encoding: Encoding) -> String {
+	let bytes = match byte_order_mark(bytes) {
+		Some((bom_encoding, length)) if bom_encoding == encoding => &bytes[length..],
+		_ => bytes,
+	};
+	decode_without_bom(bytes, encoding)
+}
+
+/// Decodes input that arrives in chunks, holding back any bytes at the end
+/// of a chunk that are only part of a character.
+pub struct Decoder {
+	encoding: Encoding,
+	pending: Vec<u8>,
+	started: bool,
+}
+
+impl Decoder {
+	pub fn new(encoding: Encoding) -> Decoder {
+		Decoder { encoding, pending: Vec::new(), started: false }
+	}
+
+	pub fn encoding(&self) -> Encoding {
+		self.encoding
+	}
+
+	pub fn decode_chunk(&mut self, chunk: &[u8]) -> String {
+		self.pending.extend_from_slice(chunk);
+		let mut start = 0;
+		if !self.started {
+			let bom: &[u8] = match self.encoding {
+				Encoding::Utf8 => &[0xEF, 0xBB, 0xBF],
+				Encoding::Utf16Le => &[0xFF, 0xFE],
+				Encoding::Utf16Be => &[0xFE, 0xFF],
+				Encoding::Windows1252 => &[],
+			};
+			// Wait for enough bytes to tell whether there's a byte order mark.
+			if self.pending.len() < bom.len() && bom.starts_with(&self.pending) {
+				return String::new();
+			}
+			if self.pending.starts_with(bom) {
+				start = bom.len();
+			}
+			self.started = true;
+		}
+		let end = start + complete_prefix_len(&self.pending[start..], self.encoding);
+		let decoded = decode_without_bom(&self.pending[start..end], self.encoding);
+		self.pending.drain(..end);
+		decoded
+	}
+
+	/// Decodes whatever is left at the end of the input. An incomplete
+	/// character becomes U+FFFD.
+	pub fn finish(&mut self) -> String {
+		let decoded = match self.started {
+			true => decode_without_bom(&self.pending, self.encoding),
+			false => decode(&self.pending, self.encoding),
+		};
+		self.pending.clear();
+		self.started = true;
+		decoded
+	}
+}
+
+/// The length of `bytes` without a character that's cut off at the end.
+fn complete_prefix_len(bytes: &[u8], encoding: Encoding) -> usize {
+	match encoding {
+		Encoding::Utf8 => {
+			let continuation_bytes = bytes.iter().rev().take(3).take_while(|&&byte| byte & 0xC0 == 0x80).count();
+			let lead = bytes.len() - continuation_bytes;
+			let expected_len = match bytes.get(lead.wrapping_sub(1)) {
+				Some(byte) if byte & 0xE0 == 0xC0 => 2,
+				Some(byte) if byte & 0xF0 == 0xE0 => 3,
+				Some(byte) if byte & 0xF8 == 0xF0 => 4,
+				_ => return bytes.len(),
+			};
+			match continuation_bytes + 1 < expected_len {
+				true => lead - 1,
+				false => bytes.len(),
+			}
+		}
+		Encoding::Utf16Le | Encoding::Utf16Be => {
+			let end = bytes.len() - bytes.len() % 2;
+			let last_unit = match (bytes.get(end.wrapping_sub(2)), bytes.get(end.wrapping_sub(1))) {
+				(Some(&first), Some(&second)) if encoding == Encoding::Utf16Le => u16::from_le_bytes([first, second]),
+				(Some(&first), Some(&second)) => u16::from_be_bytes([first, second]),
+				_ => return end,
+			};
+			// A high surrogate needs the low surrogate that follows it.
+			match (0xD800..=0xDBFF).contains(&last_unit) {
+				true => end - 2,
+				false => end,
+			}
+		}
+		Encoding::Windows1252 => bytes.len(),
+	}
+}
+
+fn decode_without_bom(bytes: &[u8], encoding: Encoding) -> String {
+	match encoding {
+		Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+		Encoding::Utf16Le => decode_utf16(bytes, u16::from_le_bytes),
+		Encoding::Utf16Be => decode_utf16(bytes, u16::from_be_bytes),
+		Encoding::Windows1252 => bytes.iter().map(|&byte| windows_1252(byte)).collect(),
+	}
+}
+
+/// Decodes a single windows-1252 byte. It matches ISO-8859-1 except for
+/// the C1 control range, where most bytes are printable characters instead.
+pub fn windows_1252(byte: u8) -> char {
+	match byte {
+		0x80 => '\u{20AC}',
+		0x82 => '\u{201A}',
+		0x83 => '\u{0192}',
+		0x84 => '\u{201E}',
+		0x85 => '\u{2026}',
+		0x86 => '\u{2020}',
+		0x87 => '\u{2021}',
+		0x88 => '\u{02C6}',
+		0x89 => '\u{2030}',
+		0x8A => '\u{0160}',
+		0x8B => '\u{2039}',
+		0x8C => '\u{0152}',
+		0x8E => '\u{017D}',
+		0x91 => '\u{2018}',
+		0x92 => '\u{2019}',
+		0x93 => '\u{201C}',
+		0x94 => '\u{201D}',
+		0x95 => '\u{2022}',
+		0x96 => '\u{2013}',
+		0x97 => '\u{2014}',
+		0x98 => '\u{02DC}',
+		0x99 => '\u{2122}',
+		0x9A => '\u{0161}',
+		0x9B => '\u{203A}',
+		0x9C => '\u{0153}',
+		0x9E => '\u{017E}',
+		0x9F => '\u{0178}',
+		_ => char::from(byte),
+	}
+}
+
+fn decode_utf16(bytes: &[u8], to_unit: fn([u8; 2]) -> u16) -> String {
+	let units = bytes.chunks_exact(2).map(|pair| to_unit([pair[0], pair[1]]));
+	let mut decoded: String = char::decode_utf16(units)
+		.map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+		.collect();
+	if bytes.len() % 2 == 1 {
+		decoded.push(char::REPLACEMENT_CHARACTER);
+	}
+	decoded
+}
+
+fn byte_order_mark(bytes: &[u8]) -> Option<(Encoding, usize)> {
+	if bytes.starts_with(&[0xEF, 0xBB, 0xBF]) {
+		Some((Encoding::Utf8, 3))
+	} else if bytes.starts_with(&[0xFE, 0xFF]) {
+		Some((Encoding::Utf16Be, 2))
+	} else if bytes.starts_with(&[0xFF, 0xFE]) {
+		Some((Encoding::Utf16Le, 2))
+	} else {
+		None
+	}
+}
+
+fn is_whitespace_byte(byte: u8) -> bool {
+	matches!(byte, b'\t' | b'\n' | b'\x0C' | b'\r' | b' ')
+}
+
+/// The HTML spec's prescan for a character encoding declaration, which
+/// skips comments and other tags looking for a `<meta>` that names one.
+fn prescan(bytes: &[u8]) -> Option<Encoding> {
+	let bytes = &bytes[..bytes.len().min(1024)];
+	let mut position = 0;
+	while position < bytes.len() {
+		let rest = &bytes[position..];
+		if rest.starts_with(b"<!--") {
+			// The dashes of `<!--` count towards the `-->`, so `<!-->` is a
+			// whole comment.
+			position += 2 + find(&rest[2..], b"-->")? + 2;
+		} else if rest.len() > 5 && rest[..5].eq_ignore_ascii_case(b"<meta") && (is_whitespace_byte(rest[5]) || rest[5] == b'/') {
+			position += 5;
+			if let Some(encoding) = meta_encoding(bytes, &mut position) {
+				return Some(encoding);
+			}
+		} else if rest.len() > 2 && rest[0] == b'<' && (rest[1].is_ascii_alphabetic() || (rest[1] == b'/' && rest[2].is_ascii_alphabetic())) {
+			while position < bytes.len() && !is_whitespace_byte(bytes[position]) && bytes[position] != b'>' {
+				position += 1;
+			}
+			while get_attribute(bytes, &mut position).is_some() {}
+		} else if rest.starts_with(b"<!") || rest.starts_with(b"</") || rest.starts_with(b"<?") {
+			position += find(rest, b">")?;
+		}
+		position += 1;
+	}
+	None
+}
+
+/// Reads the attributes of a `<meta>` tag, returning the encoding it
+/// declares if any.
+fn meta_encoding(bytes: &[u8], position: &mut usize) -> Option<Encoding> {
+	let mut seen = Vec::new();
+	let mut got_pragma = false;
+	let mut need_pragma = None;
+	let mut charset = None;
+	while let Some((name, value)) = get_attribute(bytes, position) {
+		if seen.contains(&name) {
+			continue;
+		}
+		match name.as_str() {
+			"http-equiv" if value == "content-type" => got_pragma = true,
+			"content" if charset.is_none() => {
+				if let Some(encoding) = charset_from_content(&value).and_then(Encoding::for_label) {
+					charset = Some(encoding);
+					need_pragma = Some(true);
+				}
+			}
+			"charset" => {
+				charset = Encoding::for_label(&value);
+				need_pragma = Some(false);
+			}
+			_ => {}
+		}
+		seen.push(name);
+	}
+	match (need_pragma, charset) {
+		(Some(true), _) if !got_pragma => None,
+		// A document can't declare itself UTF-16, since the declaration
+		// itself was readable as ASCII.
+		(Some(_), Some(Encoding::Utf16Le | Encoding::Utf16Be)) => Some(Encoding::Utf8),
+		(Some(_), charset) => charset,
+		(None, _) => None,
+	}
+}
+
+/// The spec's "get an attribute" algorithm for the prescan. Names and
+/// values are lowercased; `None` means there are no more attributes.
+fn get_attribute(bytes: &[u8], position: &mut usize) -> Option<(String, String)> {
+	let byte_at = |position: usize| bytes.get(position).copied();
+	while byte_at(*position).is_some_and(|byte| is_whitespace_byte(byte) || byte == b'/') {
+		*position += 1;
+	}
+	let mut name = String::new();
+	let mut value = String::new();
+	loop {
+		match byte_at(*position)? {
+			b'>' if name.is_empty() => return None,
+			b'=' if !name.is_empty() => {
+				*position += 1;
+				break;
+			}
+			byte if is_whitespace_byte(byte) => {
+				while byte_at(*position).is_some_and(is_whitespace_byte) {
+					*position += 1;
+				}
+				if byte_at(*position)? != b'=' {
+					return Some((name, value));
+				}
+				*position += 1;
+				break;
+			}
+			b'/' | b'>' => return Some((name, value)),
+			byte => {
+				name.push(char::from(byte.to_ascii_lowercase()));
+				*position += 1;
+			}
+		}
+	}
+	while byte_at(*position).is_some_and(is_whitespace_byte) {
+		*position += 1;
+	}
+	match byte_at(*position)? {
+		quote @ (b'"' | b'\'') => loop {
+			*position += 1;
+			match byte_at(*position)? {
+				byte if byte == quote => {
+					*position += 1;
+					return Some((name, value));
+				}
+				byte => value.push(char::from(byte.to_ascii_lowercase())),
+			}
+		},
+		b'>' => Some((name, value)),
+		_ => {
+			loop {
+				match byte_at(*position)? {
+					byte if is_whitespace_byte(byte) || byte == b'>' => break,
+					byte => value.push(char::from(byte.to_ascii_lowercase())),
+				}
+				*position += 1;
+			}
+			Some((name, value))
+		}
+	}
+}
+
+/// Finds the encoding label in a `content` attribute like
+/// `text/html; charset=iso-8859-1`.
+fn charset_from_content(content: &str) -> Option<&str> {
+	let mut rest = content;
+	loop {
+		rest = &rest[rest.find("charset")? + "charset".len()..];
+		let after_name = rest.trim_start_matches(|c: char| c.is_ascii_whitespace());
+		if let Some(after_equals) = after_name.strip_prefix('=') {
+			let value = after_equals.trim_start_matches(|c: char| c.is_ascii_whitespace());
+			return match value.chars().next()? {
+				quote @ ('"' | '\'') => {
+					let value = &value[1..];
+					value.find(quote).map(|end| &value[..end])
+				}
+				_ => value.split(|c: char| c.is_ascii_whitespace() || c == ';').next(),
+			};
+		}
+	}
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+	haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn byte_order_marks() {
+		assert_eq!(sniff(b"\xEF\xBB\xBFcaf\xC3\xA9", None), Encoding::Utf8);
+		assert_eq!(sniff(b"\xFF\xFEh\0i\0", None), Encoding::Utf16Le);
+		assert_eq!(sniff(b"\xFE\xFF\0h\0i", None), Encoding::Utf16Be);
+		assert_eq!(decode(b"\xEF\xBB\xBFcaf\xC3\xA9", Encoding::Utf8), "caf\u{E9}");
+		assert_eq!(decode(b"\xFF\xFEh\0i\0", Encoding::Utf16Le), "hi");
+		assert_eq!(decode(b"\xFE\xFF\0h\0i", Encoding::Utf16Be), "hi");
+	}
+
+	#[test]
+	fn meta_declarations() {
+		assert_eq!(sniff(b"<!DOCTYPE html><meta charset=\"Latin1\">caf\xC3\xA9", None), Encoding::Windows1252);
+		assert_eq!(
+			sniff(b"<head><meta http-equiv=Content-Type content='text/html; charset=windows-1252'>", None),
+			Encoding::Windows1252
+		);
+		// Without the `http-equiv`, the `content` doesn't count.
+		assert_eq!(sniff(b"<meta content='text/html; charset=windows-1252'>", None), Encoding::Utf8);
+		assert_eq!(sniff(b"<meta charset=utf-16le>", None), Encoding::Utf8);
+		assert_eq!(sniff(b"<p title='<meta charset=latin1>'>", None), Encoding::Utf8);
+		assert_eq!(sniff(b"<!-- <meta charset=latin1> --><p>caf\xC3\xA9", None), Encoding::Utf8);
+		assert_eq!(sniff(b"<!-- <meta charset=latin1> --><p>caf\xE9", None), Encoding::Windows1252);
+		assert_eq!(sniff(b"<!-- <meta charset=latin1> --><meta charset=utf-8>caf\xE9", None), Encoding::Utf8);
+	}
+
+	#[test]
+	fn hints_override_meta_and_byte_order_marks_override_hints() {
+		assert_eq!(sniff(b"<meta charset=utf-8>", Some(Encoding::Windows1252)), Encoding::Windows1252);
+		assert_eq!(sniff(b"\xEF\xBB\xBF<meta charset=latin1>", Some(Encoding::Windows1252)), Encoding::Utf8);
+		assert_eq!(sniff(b"\xFF\xFE<\0", Some(Encoding::Utf8)), Encoding::Utf16Le);
+	}
+
+	#[test]
+	fn windows_1252_c1_range() {
+		let bytes: Vec<u8> = (0x80..=0x9F).collect();
+		assert_eq!(
+			decode(&bytes, Encoding::Windows1252),
+			"\u{20AC}\u{81}\u{201A}\u{192}\u{201E}\u{2026}\u{2020}\u{2021}\u{2C6}\u{2030}\u{160}\u{2039}\u{152}\u{8D}\u{17D}\u{8F}\
+			 \u{90}\u{2018}\u{2019}\u{201C}\u{201D}\u{2022}\u{2013}\u{2014}\u{2DC}\u{2122}\u{161}\u{203A}\u{153}\u{9D}\u{17E}\u{178}"
+		);
+		assert_eq!(decode(b"caf\xE9 \xA0", Encoding::Windows1252), "caf\u{E9} \u{A0}");
+	}
+
+	/// Decodes `bytes` a byte at a time.
+	fn decode_bytewise(bytes: &[u8], encoding: Encoding) -> String {
+		let mut decoder = Decoder::new(encoding);
+		let mut decoded: String = bytes.iter().map(|&byte| decoder.decode_chunk(&[byte])).collect();
+		decoded.push_str(&decoder.finish());
+		decoded
+	}
+
+	#[test]
+	fn characters_split_across_chunks() {
+		let text = "a\u{E9}\u{20AC}\u{1F600}";
+		assert_eq!(decode_bytewise(text.as_bytes(), Encoding::Utf8), text);
+		let with_bom = [&[0xEF, 0xBB, 0xBF], text.as_bytes()].concat();
+		assert_eq!(decode_bytewise(&with_bom, Encoding::Utf8), text);
+
+		let le: Vec<u8> = text.encode_utf16().flat_map(u16::to_le_bytes).collect();
+		assert_eq!(decode_bytewise(&[&[0xFF, 0xFE], &le[..]].concat(), Encoding::Utf16Le), text);
+		let be: Vec<u8> = text.encode_utf16().flat_map(u16::to_be_bytes).collect();
+		assert_eq!(decode_bytewise(&be, Encoding::Utf16Be), text);
+
+		// A character cut off by the end of the input is replaced.
+		assert_eq!(decode_bytewise(&text.as_bytes()[..text.len() - 1], Encoding::Utf8), "a\u{E9}\u{20AC}\u{FFFD}");
+		assert_eq!(decode_bytewise(&be[..be.len() - 2], Encoding::Utf16Be), "a\u{E9}\u{20AC}\u{FFFD}");
+	}
+}
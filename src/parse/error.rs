@@ -0,0 +1,82 @@
+use std::{error::Error, fmt};
+
+use crate::parse::span::Span;
+
+pub type ParseResult<T> = Result<T, ParseError>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    UnexpectedChar {
+        expected: String,
+        found: char,
+        span: Span,
+    },
+    UnexpectedEof {
+        expected: String,
+        span: Span,
+    },
+    InvalidNumber {
+        value: String,
+        span: Span,
+    },
+    UnrecognizedUnit {
+        unit: String,
+        span: Span,
+    },
+    InvalidColor {
+        value: String,
+        span: Span,
+    },
+    UnsupportedAtRule {
+        name: String,
+        span: Span,
+    },
+    /// A recoverable syntax error, named with the error code the HTML spec
+    /// uses for it (e.g. `eof-in-tag`).
+    Syntax {
+        code: &'static str,
+        span: Span,
+    },
+}
+
+impl ParseError {
+    pub fn span(&self) -> &Span {
+        match self {
+            ParseError::UnexpectedChar { span, .. }
+            | ParseError::UnexpectedEof { span, .. }
+            | ParseError::InvalidNumber { span, .. }
+            | ParseError::UnrecognizedUnit { span, .. }
+            | ParseError::InvalidColor { span, .. }
+            | ParseError::UnsupportedAtRule { span, .. }
+            | ParseError::Syntax { span, .. } => span,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedChar { expected, found, span } => {
+                write!(f, "{}: expected {} but found '{}'", span, expected, found)
+            }
+            ParseError::UnexpectedEof { expected, span } => {
+                write!(f, "{}: expected {} but reached end of input", span, expected)
+            }
+            ParseError::InvalidNumber { value, span } => {
+                write!(f, "{}: invalid number '{}'", span, value)
+            }
+            ParseError::UnrecognizedUnit { unit, span } => {
+                write!(f, "{}: unrecognized unit '{}'", span, unit)
+            }
+            ParseError::InvalidColor { value, span } => {
+                write!(f, "{}: invalid color '{}'", span, value)
+            }
+            ParseError::UnsupportedAtRule { name, span } => {
+                write!(f, "{}: unsupported at-rule '@{}'", span, name)
+            }
+            ParseError::Syntax { code, span } => write!(f, "{}: {}", span, code),
+        }
+    }
+}
+
+impl Error for ParseError {}
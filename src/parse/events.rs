@@ -0,0 +1,390 @@
+use std::{collections::HashMap, fmt, rc::Rc};
+
+use crate::parse::dom::{Document, NodeId};
+
+/// Where an event is on its way through the document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventPhase {
+	/// The event isn't being dispatched.
+	None,
+	/// Going down from the root to the target's parent.
+	Capturing,
+	AtTarget,
+	/// Going back up from the target's parent to the root.
+	Bubbling,
+}
+
+/// What happened, for events that come from input.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventDetail {
+	None,
+	/// `x` and `y` are where the pointer was, and `button` which button
+	/// changed, numbered like the DOM's `MouseEvent.button`.
+	Mouse { x: f64, y: f64, button: u32 },
+	/// `key` is the character typed, or a name like `Enter` for keys that
+	/// don't type one.
+	Key { key: String },
+}
+
+#[derive(Debug, Clone)]
+pub struct Event {
+	event_type: String,
+	bubbles: bool,
+	cancelable: bool,
+	pub detail: EventDetail,
+	target: Option<NodeId>,
+	current_target: Option<NodeId>,
+	phase: EventPhase,
+	propagation_stopped: bool,
+	immediate_propagation_stopped: bool,
+	default_prevented: bool,
+}
+
+impl Event {
+	pub fn new(event_type: &str, bubbles: bool, cancelable: bool) -> Event {
+		Event {
+			event_type: event_type.to_string(),
+			bubbles,
+			cancelable,
+			detail: EventDetail::None,
+			target: None,
+			current_target: None,
+			phase: EventPhase::None,
+			propagation_stopped: false,
+			immediate_propagation_stopped: false,
+			default_prevented: false,
+		}
+	}
+
+	/// A mouse event such as `click` or `mousemove`. These bubble and can
+	/// be canceled.
+	pub fn mouse(event_type: &str, x: f64, y: f64, button: u32) -> Event {
+		let mut event = Event::new(event_type, true, true);
+		event.detail = EventDetail::Mouse { x, y, button };
+		event
+	}
+
+	/// A keyboard event such as `keydown`. These bubble and can be
+	/// canceled.
+	pub fn key(event_type: &str, key: &str) -> Event {
+		let mut event = Event::new(event_type, true, true);
+		event.detail = EventDetail::Key { key: key.to_string() };
+		event
+	}
+
+	pub fn event_type(&self) -> &str {
+		&self.event_type
+	}
+
+	pub fn bubbles(&self) -> bool {
+		self.bubbles
+	}
+
+	pub fn cancelable(&self) -> bool {
+		self.cancelable
+	}
+
+	/// The node the event was dispatched to.
+	pub fn target(&self) -> Option<NodeId> {
+		self.target
+	}
+
+	/// The node whose listeners are being called.
+	pub fn current_target(&self) -> Option<NodeId> {
+		self.current_target
+	}
+
+	pub fn phase(&self) -> EventPhase {
+		self.phase
+	}
+
+	/// Stops the event going on to other nodes once the current node's
+	/// listeners have been called.
+	pub fn stop_propagation(&mut self) {
+		self.propagation_stopped = true;
+	}
+
+	/// Stops the event going on to any other listener, even on the current
+	/// node.
+	pub fn stop_immediate_propagation(&mut self) {
+		self.propagation_stopped = true;
+		self.immediate_propagation_stopped = true;
+	}
+
+	/// Asks for whatever the event would normally cause not to happen. Does
+	/// nothing if the event can't be canceled.
+	pub fn prevent_default(&mut self) {
+		if self.cancelable {
+			self.default_prevented = true;
+		}
+	}
+
+	pub fn default_prevented(&self) -> bool {
+		self.default_prevented
+	}
+}
+
+/// Identifies a listener, so it can be removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ListenerId(usize);
+
+/// A listener is given the document, so it can change it in response to
+/// the event.
+type Callback = dyn Fn(&mut Document, &mut Event);
+
+struct Listener {
+	id: ListenerId,
+	event_type: String,
+	capture: bool,
+	callback: Rc<Callback>,
+}
+
+impl fmt::Debug for Listener {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("Listener")
+			.field("id", &self.id)
+			.field("event_type", &self.event_type)
+			.field("capture", &self.capture)
+			.finish_non_exhaustive()
+	}
+}
+
+/// The event listeners of every node in a document.
+#[derive(Debug, Default)]
+pub(crate) struct Listeners {
+	next_id: usize,
+	by_node: HashMap<NodeId, Vec<Listener>>,
+}
+
+/// Events, following the DOM's event dispatch.
+impl Document {
+	/// Calls `listener` for every `event_type` event dispatched to `id` or,
+	/// if it bubbles, to a node under it. Capturing listeners are called on
+	/// the way down to the target rather than on the way back up.
+	pub fn add_event_listener<F>(&mut self, id: NodeId, event_type: &str, capture: bool, listener: F) -> ListenerId
+	where
+		F: Fn(&mut Document, &mut Event) + 'static,
+	{
+		let listeners = &mut self.listeners;
+		let listener_id = ListenerId(listeners.next_id);
+		listeners.next_id += 1;
+		listeners.by_node.entry(id).or_default().push(Listener {
+			id: listener_id,
+			event_type: event_type.to_string(),
+			capture,
+			callback: Rc::new(listener),
+		});
+		listener_id
+	}
+
+	/// Returns whether `listener` was listening to `id`. A listener removed
+	/// during a dispatch isn't called again.
+	pub fn remove_event_listener(&mut self, id: NodeId, listener: ListenerId) -> bool {
+		let Some(listeners) = self.listeners.by_node.get_mut(&id) else {
+			return false;
+		};
+		let count = listeners.len();
+		listeners.retain(|registered| registered.id != listener);
+		listeners.len() != count
+	}
+
+	/// Sends `event` to `target` and its ancestors: first down from the root
+	/// to the capturing listeners, then to the target's own listeners, and
+	/// then back up to the root if the event bubbles. Returns `false` if a
+	/// listener canceled the event.
+	pub fn dispatch_event(&mut self, target: NodeId, event: &mut Event) -> bool {
+		event.target = Some(target);
+		let ancestors: Vec<NodeId> = self.ancestors(target).collect();
+		let bubbling: &[NodeId] = match event.bubbles {
+			true => &ancestors,
+			false => &[],
+		};
+		let path = ancestors
+			.iter()
+			.rev()
+			.map(|&id| (id, EventPhase::Capturing))
+			.chain([(target, EventPhase::AtTarget)])
+			.chain(bubbling.iter().map(|&id| (id, EventPhase::Bubbling)));
+		for (id, phase) in path {
+			if event.propagation_stopped {
+				break;
+			}
+			self.invoke_listeners(id, phase, event);
+		}
+		event.current_target = None;
+		event.phase = EventPhase::None;
+		event.propagation_stopped = false;
+		event.immediate_propagation_stopped = false;
+		!event.default_prevented
+	}
+
+	fn invoke_listeners(&mut self, id: NodeId, phase: EventPhase, event: &mut Event) {
+		event.current_target = Some(id);
+		event.phase = phase;
+		// Listeners added while these are called wait for the next event.
+		let mut listeners: Vec<(bool, ListenerId, Rc<Callback>)> = self
+			.listeners
+			.by_node
+			.get(&id)
+			.into_iter()
+			.flatten()
+			.filter(|listener| listener.event_type == event.event_type)
+			.filter(|listener| match phase {
+				EventPhase::Capturing => listener.capture,
+				EventPhase::Bubbling => !listener.capture,
+				_ => true,
+			})
+			.map(|listener| (listener.capture, listener.id, Rc::clone(&listener.callback)))
+			.collect();
+		// At the target, capturing listeners still go first.
+		listeners.sort_by_key(|&(capture, _, _)| !capture);
+		for (_, listener_id, callback) in listeners {
+			if !self.has_listener(id, listener_id) {
+				continue;
+			}
+			callback(self, event);
+			if event.immediate_propagation_stopped {
+				break;
+			}
+		}
+	}
+
+	fn has_listener(&self, id: NodeId, listener: ListenerId) -> bool {
+		self.listeners
+			.by_node
+			.get(&id)
+			.is_some_and(|listeners| listeners.iter().any(|registered| registered.id == listener))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::cell::RefCell;
+
+	use super::*;
+	use crate::parse::html;
+
+	type Log = Rc<RefCell<Vec<String>>>;
+
+	/// A document with `<div><p><b>x</b></p></div>` in its body, and the
+	/// `div`, `p` and `b`.
+	fn document() -> (Document, [NodeId; 3]) {
+		let document = Document::from(html::parse("<div><p><b>x</b></p></div>".to_string()));
+		let b = document.get_elements_by_tag_name(document.root(), "b")[0];
+		let p = document[b].parent().unwrap();
+		let div = document[p].parent().unwrap();
+		(document, [div, p, b])
+	}
+
+	/// Adds a `click` listener that logs `name` and the phase it was called
+	/// in, then does whatever `then` does.
+	fn listen<F>(document: &mut Document, id: NodeId, capture: bool, name: &str, log: &Log, then: F) -> ListenerId
+	where
+		F: Fn(&mut Document, &mut Event) + 'static,
+	{
+		let (name, log) = (name.to_string(), Rc::clone(log));
+		document.add_event_listener(id, "click", capture, move |document, event| {
+			assert_eq!(event.current_target(), Some(id));
+			log.borrow_mut().push(format!("{} {:?}", name, event.phase()));
+			then(document, event);
+		})
+	}
+
+	fn nothing(_: &mut Document, _: &mut Event) {}
+
+	#[test]
+	fn capture_then_target_then_bubble() {
+		let (mut document, [div, p, b]) = document();
+		let log = Log::default();
+		listen(&mut document, div, false, "div", &log, nothing);
+		listen(&mut document, div, true, "div", &log, nothing);
+		listen(&mut document, p, false, "p", &log, nothing);
+		listen(&mut document, p, true, "p", &log, nothing);
+		listen(&mut document, b, false, "b bubble", &log, nothing);
+		listen(&mut document, b, true, "b capture", &log, nothing);
+		document.add_event_listener(b, "keydown", false, |_, _| panic!("not a click"));
+
+		let mut event = Event::mouse("click", 0.0, 0.0, 0);
+		assert!(document.dispatch_event(b, &mut event));
+		assert_eq!(
+			*log.borrow(),
+			[
+				"div Capturing",
+				"p Capturing",
+				"b capture AtTarget",
+				"b bubble AtTarget",
+				"p Bubbling",
+				"div Bubbling",
+			]
+		);
+		assert_eq!(event.target(), Some(b));
+		assert_eq!(event.current_target(), None);
+		assert_eq!(event.phase(), EventPhase::None);
+	}
+
+	#[test]
+	fn stop_propagation_finishes_the_current_node() {
+		let (mut document, [div, p, b]) = document();
+		let log = Log::default();
+		listen(&mut document, div, false, "div", &log, nothing);
+		listen(&mut document, p, false, "p 1", &log, |_, event| event.stop_propagation());
+		listen(&mut document, p, false, "p 2", &log, nothing);
+		document.dispatch_event(b, &mut Event::mouse("click", 0.0, 0.0, 0));
+		assert_eq!(*log.borrow(), ["p 1 Bubbling", "p 2 Bubbling"]);
+
+		// The next dispatch of the same event starts afresh.
+		log.borrow_mut().clear();
+		let mut event = Event::mouse("click", 0.0, 0.0, 0);
+		document.dispatch_event(div, &mut event);
+		document.dispatch_event(div, &mut event);
+		assert_eq!(*log.borrow(), ["div AtTarget", "div AtTarget"]);
+	}
+
+	#[test]
+	fn stop_immediate_propagation_stops_the_current_node_too() {
+		let (mut document, [div, p, b]) = document();
+		let log = Log::default();
+		listen(&mut document, div, false, "div", &log, nothing);
+		listen(&mut document, p, false, "p 1", &log, |_, event| event.stop_immediate_propagation());
+		listen(&mut document, p, false, "p 2", &log, nothing);
+		document.dispatch_event(b, &mut Event::mouse("click", 0.0, 0.0, 0));
+		assert_eq!(*log.borrow(), ["p 1 Bubbling"]);
+	}
+
+	#[test]
+	fn events_that_dont_bubble_stop_at_the_target() {
+		let (mut document, [div, p, b]) = document();
+		let log = Log::default();
+		listen(&mut document, div, true, "div", &log, nothing);
+		listen(&mut document, p, false, "p", &log, nothing);
+		listen(&mut document, b, false, "b", &log, nothing);
+		let mut event = Event::new("click", false, false);
+		event.prevent_default();
+		assert!(document.dispatch_event(b, &mut event));
+		assert_eq!(*log.borrow(), ["div Capturing", "b AtTarget"]);
+	}
+
+	#[test]
+	fn listeners_can_change_the_document_while_dispatching() {
+		let (mut document, [div, p, b]) = document();
+		let log = Log::default();
+		let div_listener = listen(&mut document, div, false, "div", &log, nothing);
+		let added_log = Rc::clone(&log);
+		listen(&mut document, b, false, "b", &log, move |document, event| {
+			document.set_text_content(div, "gone");
+			document.remove_event_listener(div, div_listener);
+			// Only the listeners of nodes the event hasn't reached yet are
+			// looked at afresh.
+			document.add_event_listener(b, "click", false, |_, _| panic!("added to the current node"));
+			let added_log = Rc::clone(&added_log);
+			document.add_event_listener(p, "click", false, move |_, _| added_log.borrow_mut().push("p added".to_string()));
+			event.prevent_default();
+		});
+		listen(&mut document, p, false, "p", &log, move |document, _| {
+			document.remove_child(div, p).unwrap_err();
+		});
+		assert!(!document.dispatch_event(b, &mut Event::mouse("click", 0.0, 0.0, 0)));
+		assert_eq!(*log.borrow(), ["b AtTarget", "p Bubbling", "p added"]);
+		assert_eq!(document.text_content(div), "gone");
+	}
+}
@@ -1,119 +1,230 @@
-use std::{collections::HashMap};
+use std::io::{self, Read};
 
-use crate::parse::{dom, parser::Parser};
+use crate::parse::{
+	dom,
+	encoding::{self, Decoder, Encoding},
+	error::ParseError,
+	tokenizer::{State, Token, Tokenizer},
+	tree_builder::TreeBuilder,
+};
+
+/// Elements that never have children or an end tag.
+pub const VOID_ELEMENTS: [&str; 14] = [
+	"area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+	"track", "wbr",
+];
+
+pub fn is_void_element(tag_name: &str) -> bool {
+	VOID_ELEMENTS.iter().any(|void| void.eq_ignore_ascii_case(tag_name))
+}
 
 pub fn parse(source: String) -> dom::Node {
-	let mut nodes = HTMLParser::new(source).parse_nodes();
-	
-	if nodes.len()== 1 {
-		nodes.swap_remove(0)
-	} else {
-		dom::element("html".to_string(), HashMap::new(), nodes)
-	}
+	parse_with_errors(source, None).0
 }
 
-struct HTMLParser {
-	parser: Parser
+/// Like `parse`, but records `file_name` in the span of every node.
+pub fn parse_named(source: String, file_name: &str) -> dom::Node {
+	parse_with_errors(source, Some(file_name)).0
 }
 
-impl HTMLParser {
-	fn new(input: String) -> HTMLParser {
-		HTMLParser { parser: Parser::new(0, input) }
-	}
-	
-	fn parse_node(&mut self) -> dom::Node {
-		match self.parser.next_char() {
-				'<' => self.parse_element_or_comment(),
-				_ => self.parse_text(),
+/// Parses a document the way a browser would, recovering from misnested
+/// and unclosed tags. Everything that had to be recovered from is reported
+/// in the returned errors.
+pub fn parse_with_errors(source: String, file_name: Option<&str>) -> (dom::Node, Vec<ParseError>) {
+	let tokenizer = match file_name {
+		Some(file_name) => Tokenizer::with_file(source, file_name),
+		None => Tokenizer::new(source),
+	};
+	TreeBuilder::new(tokenizer).build()
+}
+
+/// Parses a document from raw bytes, working out its encoding from a byte
+/// order mark, `encoding_hint` or a `<meta charset>` as `encoding::sniff`
+/// describes.
+pub fn parse_bytes(bytes: &[u8], encoding_hint: Option<Encoding>) -> dom::Node {
+	parse_bytes_with_errors(bytes, encoding_hint, None).0
+}
+
+pub fn parse_bytes_with_errors(
+	bytes: &[u8],
+	encoding_hint: Option<Encoding>,
+	file_name: Option<&str>,
+) -> (dom::Node, Vec<ParseError>) {
+	let encoding = encoding::sniff(bytes, encoding_hint);
+	parse_with_errors(encoding::decode(bytes, encoding), file_name)
+}
+
+/// Parses a document from `reader` a chunk at a time, without needing the
+/// whole of it in memory first.
+pub fn parse_reader<R: Read>(
+	mut reader: R,
+	encoding_hint: Option<Encoding>,
+	file_name: Option<&str>,
+) -> io::Result<(dom::Node, Vec<ParseError>)> {
+	let mut parser = StreamingParser::new(encoding_hint, file_name);
+	let mut buffer = [0; 8192];
+	loop {
+		match reader.read(&mut buffer) {
+			Ok(0) => break,
+			Ok(read) => parser.push_bytes(&buffer[..read]),
+			Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+			Err(error) => return Err(error),
 		}
 	}
-	
-	fn parse_element_or_comment(&mut self) -> dom::Node {
-		assert!(self.parser.consume_char() == '<');
-		match self.parser.next_char() {
-			'!' => self.parse_comment(),
-			_ => self.parse_element(),
+	Ok(parser.finish())
+}
+
+/// Parses a document that arrives in chunks. Tokens split across chunks are
+/// kept until they're complete, and `document` shows what's been parsed so
+/// far, so a page can be displayed while it's still loading.
+pub struct StreamingParser {
+	builder: TreeBuilder,
+	encoding_hint: Option<Encoding>,
+	decoder: Option<Decoder>,
+	undecoded: Vec<u8>,
+}
+
+impl StreamingParser {
+	pub fn new(encoding_hint: Option<Encoding>, file_name: Option<&str>) -> StreamingParser {
+		StreamingParser {
+			builder: TreeBuilder::new(Tokenizer::streaming(file_name)),
+			encoding_hint,
+			decoder: None,
+			undecoded: Vec::new(),
 		}
 	}
-	
-	fn parse_comment(&mut self) -> dom::Node {
-		assert!(self.parser.consume_char() == '!');
-		loop {
-			let delimiter = self.parser.consume_char();
-			if delimiter == '-' && self.parser.next_char() != delimiter {
-				break;
+
+	pub fn push_str(&mut self, chunk: &str) {
+		self.builder.push_str(chunk);
+	}
+
+	/// Pushes undecoded input. The encoding is sniffed from the first 1024
+	/// bytes, so nothing is parsed until that many have arrived.
+	pub fn push_bytes(&mut self, chunk: &[u8]) {
+		match self.decoder {
+			Some(ref mut decoder) => {
+				let text = decoder.decode_chunk(chunk);
+				self.builder.push_str(&text);
+			}
+			None => {
+				self.undecoded.extend_from_slice(chunk);
+				if self.undecoded.len() >= 1024 {
+					self.start_decoding(false);
+				}
 			}
 		}
-		self.parser.consume_whitespace();
-		let comment = self.parser.consume_while(|c| c != '-');
-		
-		assert!(self.parser.consume_char() == '-');
-    assert!(self.parser.consume_char() == '-');
-    assert!(self.parser.consume_char() == '>');
-		return dom::comment(comment.trim_end().to_string());
 	}
-	
-	fn parse_text(&mut self) -> dom::Node {
-		dom::text(self.parser.consume_while(|c| c != '<'))
+
+	/// The encoding the input is being decoded from, once it's been sniffed.
+	pub fn encoding(&self) -> Option<Encoding> {
+		self.decoder.as_ref().map(Decoder::encoding)
 	}
-	
-	fn parse_element(&mut self) -> dom::Node {
-		let tag_name = self.parse_tag_name();
-		let attributes = self.parse_attributes();
-		assert!(self.parser.consume_char() == '>');
-		
-		let children = self.parse_nodes();
-		
-		assert!(self.parser.consume_char() == '<');
-    assert!(self.parser.consume_char() == '/');
-    assert!(self.parse_tag_name() == tag_name);
-    assert!(self.parser.consume_char() == '>');
-		
-		dom::element(tag_name, attributes, children)
+
+	fn start_decoding(&mut self, finished: bool) -> &mut Decoder {
+		let encoding = match finished {
+			true => encoding::sniff(&self.undecoded, self.encoding_hint),
+			false => encoding::sniff_start(&self.undecoded, self.encoding_hint),
+		};
+		let mut decoder = Decoder::new(encoding);
+		let text = decoder.decode_chunk(&std::mem::take(&mut self.undecoded));
+		self.builder.push_str(&text);
+		self.decoder.insert(decoder)
 	}
-	
-	fn parse_nodes(&mut self) -> Vec<dom::Node> {
-		let mut nodes = Vec::new();
-		loop {
-			self.parser.consume_whitespace();
-			if self.parser.ended() || self.parser.starts_with("</") {
-				break;
-			}
-			nodes.push(self.parse_node());
+
+	/// The document as parsed so far.
+	pub fn document(&self) -> dom::Node {
+		self.builder.document()
+	}
+
+	pub fn finish(mut self) -> (dom::Node, Vec<ParseError>) {
+		if self.decoder.is_some() || !self.undecoded.is_empty() {
+			let decoder = match self.decoder {
+				Some(ref mut decoder) => decoder,
+				None => self.start_decoding(true),
+			};
+			let text = decoder.finish();
+			self.builder.push_str(&text);
 		}
-		nodes
+		self.builder.finish()
 	}
-	
-	fn parse_attributes(&mut self) -> dom::AttrMap {
-		let mut attributes = HashMap::new();
-		loop {
-				self.parser.consume_whitespace();
-				if self.parser.next_char() == '>' {
-					break;
-				}
-				let (name, value) = self.parse_attr();
-				attributes.insert(name, value);
+}
+
+/// Replaces character references such as `&amp;` and `&#169;` in `text`
+/// the way they would be in an element's text content. The text is
+/// preprocessed like any other parser input too, so `\r\n` and `\r` become
+/// `\n` and NUL becomes U+FFFD.
+pub fn decode_character_references(text: &str) -> String {
+	let mut tokenizer = Tokenizer::new(text.to_string());
+	tokenizer.set_state(State::Rcdata);
+	tokenizer
+		.filter_map(|(token, _)| match token {
+			Token::Character(c) => Some(c),
+			_ => None,
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use std::path::Path;
+
+	use super::*;
+	use crate::parse::{style, stylesheets};
+
+	#[test]
+	fn streaming_matches_parsing_all_at_once() {
+		let source = "<!DOCTYPE html>\r\n<title>a &amp; b</title>\n<p class=x>one<p>tw\u{e9}\r\n<!-- c --></b>";
+		let (expected, expected_errors) = parse_with_errors(source.to_string(), Some("page.html"));
+		let mut parser = StreamingParser::new(None, Some("page.html"));
+		let chars: Vec<char> = source.chars().collect();
+		for chunk in chars.chunks(3) {
+			parser.push_str(&chunk.iter().collect::<String>());
 		}
-		attributes
+		let (document, errors) = parser.finish();
+		assert_eq!(format!("{:?}", document), format!("{:?}", expected));
+		assert_eq!(errors, expected_errors);
 	}
-	
-	fn parse_attr(&mut self) -> (String, String) {
-		let name = self.parse_tag_name();
-		assert!(self.parser.consume_char() == '=');
-		let value = self.parse_attr_value();
-		(name, value)
+
+	#[test]
+	fn short_byte_input_is_sniffed_when_finished() {
+		let mut parser = StreamingParser::new(None, None);
+		parser.push_bytes(b"<meta charset=windows-1252><p>caf");
+		parser.push_bytes(b"\xE9 \x80");
+		assert_eq!(parser.encoding(), None);
+		let (document, _) = parser.finish();
+		assert_eq!(document.text_content(), "caf\u{E9} \u{20AC}");
+
+		let mut parser = StreamingParser::new(Some(Encoding::Utf16Le), None);
+		parser.push_bytes(b"<\0p\0>\0=\xD8");
+		parser.push_bytes(b"\0\xDE");
+		assert_eq!(parser.finish().0.text_content(), "\u{1F600}");
+
+		let (document, _) = StreamingParser::new(None, None).finish();
+		assert_eq!(document.outer_html(), "<html><head></head><body></body></html>");
 	}
-	
-	fn parse_attr_value(&mut self) -> String {
-		let open_quote = self.parser.consume_char();
-		assert!(open_quote == '"' || open_quote == '\'');
-		
-		let value = self.parser.consume_while(|c| c != open_quote);
-		assert!(self.parser.consume_char() == open_quote);
-		value
+
+	#[test]
+	fn character_references_are_decoded_and_newlines_normalized() {
+		assert_eq!(decode_character_references("a &lt;b&gt; &amp;amp; &#169;&#x41;&notit; &bogus; <i>"), "a <b> &amp; ©A¬it; &bogus; <i>");
+		assert_eq!(decode_character_references("one\r\ntwo\rthree\0"), "one\ntwo\nthree\u{FFFD}");
 	}
-	
-	fn parse_tag_name(&mut self) -> String {
-		self.parser.consume_while(|character| matches!(character, 'a'..='z' | 'A'..='Z' | '0'..='9'))
+
+	#[test]
+	fn deeply_nested_documents_dont_overflow_the_stack() {
+		const DEPTH: usize = 20_000;
+		// Unlike `div`, a `span` start tag doesn't look for a `p` to close, so
+		// parsing these doesn't search the whole stack of open elements.
+		let source = format!("<style>span {{ margin: 1px }}</style>{}x{}", "<span>".repeat(DEPTH), "</span>".repeat(DEPTH));
+		let node = parse(source);
+		assert_eq!(node.text_content(), "span { margin: 1px }x");
+		assert!(node.outer_html().ends_with(&format!("x{}</body></html>", "</span>".repeat(DEPTH))));
+
+		let document = dom::Document::from(node);
+		let body = document.get_elements_by_tag_name(document.root(), "body")[0];
+		assert_eq!(document.to_node(body).text_content(), "x");
+		let (stylesheets, errors) = stylesheets::load(&document, Path::new("page.html"));
+		assert!(errors.is_empty());
+		let style_tree = style::style_tree(&document, &stylesheets);
+		drop(style_tree);
 	}
 }
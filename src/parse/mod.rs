@@ -1,6 +1,19 @@
 pub mod css;
 pub mod cssom;
 pub mod dom;
+pub mod encoding;
+pub mod entities;
+pub mod error;
+pub mod events;
 pub mod html;
+pub mod mutation;
 pub mod parser;
-pub mod style;
\ No newline at end of file
+pub mod query;
+pub mod serialize;
+pub mod span;
+pub mod style;
+pub mod stylesheets;
+pub mod tokenizer;
+pub mod traversal;
+pub mod tree_builder;
+pub mod whitespace;
\ No newline at end of file
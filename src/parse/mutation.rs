@@ -0,0 +1,411 @@
+use std::{
+	error::Error,
+	fmt,
+	sync::mpsc::{self, Receiver},
+};
+
+use crate::parse::dom::{self, Document, NodeId, NodeType};
+
+/// A change made to a document, like the records a `MutationObserver` is
+/// given.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MutationRecord {
+	/// Children were added to or removed from `target`. The siblings are
+	/// those either side of where the change was made.
+	ChildList {
+		target: NodeId,
+		added_nodes: Vec<NodeId>,
+		removed_nodes: Vec<NodeId>,
+		previous_sibling: Option<NodeId>,
+		next_sibling: Option<NodeId>,
+	},
+	/// An attribute of `target` was set or removed.
+	Attributes { target: NodeId, name: String, old_value: Option<String> },
+	/// The text of a text or comment node changed.
+	CharacterData { target: NodeId, old_value: String },
+}
+
+/// Why a change to a document wasn't made, named after the `DOMException`
+/// the DOM spec would throw.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DomError {
+	/// The change would have made an invalid tree, such as a node inside
+	/// itself or a text node with children.
+	HierarchyRequest,
+	/// The node to insert before, remove or replace isn't a child of the
+	/// given parent.
+	NotFound,
+	/// The node isn't an element, so it has no attributes.
+	InvalidNodeType,
+}
+
+impl fmt::Display for DomError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			DomError::HierarchyRequest => write!(f, "the node can't be inserted there"),
+			DomError::NotFound => write!(f, "the node isn't a child of the parent"),
+			DomError::InvalidNodeType => write!(f, "the node isn't an element"),
+		}
+	}
+}
+
+impl Error for DomError {}
+
+pub type DomResult<T> = Result<T, DomError>;
+
+/// Changing a document. Every change is sent to the document's observers
+/// as a `MutationRecord`. Removed nodes keep their ids, and can be inserted
+/// again.
+impl Document {
+	/// Returns a receiver for a record of every change made from now on.
+	/// Dropping it stops the records.
+	pub fn observe(&mut self) -> Receiver<MutationRecord> {
+		let (sender, receiver) = mpsc::channel();
+		self.observers.push(sender);
+		receiver
+	}
+
+	fn notify(&mut self, record: MutationRecord) {
+		self.observers.retain(|observer| observer.send(record.clone()).is_ok());
+	}
+
+	/// Adds `node` and all its descendants as the last child of `parent`,
+	/// returning the id `node` was given.
+	pub fn insert_tree(&mut self, parent: NodeId, node: dom::Node) -> DomResult<NodeId> {
+		// Checked before the nodes are made, so none are left behind if the
+		// insertion isn't allowed.
+		self.check_insertion(parent, None, &node.node_type, None, None)?;
+		let id = self.create_node(node);
+		self.insert(parent, id, None);
+		Ok(id)
+	}
+
+	/// Moves `node` to the end of `parent`'s children.
+	pub fn append_child(&mut self, parent: NodeId, node: NodeId) -> DomResult<NodeId> {
+		self.insert_before(parent, node, None)
+	}
+
+	/// Moves `node` into `parent`'s children before `child`, or to the end
+	/// if that's `None`.
+	pub fn insert_before(&mut self, parent: NodeId, node: NodeId, child: Option<NodeId>) -> DomResult<NodeId> {
+		self.check_insertion(parent, Some(node), &self[node].node_type, child, None)?;
+		if child == Some(node) {
+			return Ok(node);
+		}
+		self.remove(node);
+		self.insert(parent, node, child);
+		Ok(node)
+	}
+
+	/// Detaches `child` from `parent`.
+	pub fn remove_child(&mut self, parent: NodeId, child: NodeId) -> DomResult<NodeId> {
+		if self[child].parent() != Some(parent) {
+			return Err(DomError::NotFound);
+		}
+		self.remove(child);
+		Ok(child)
+	}
+
+	/// Puts `node` where `child` is in `parent`'s children, and detaches
+	/// `child`.
+	pub fn replace_child(&mut self, parent: NodeId, node: NodeId, child: NodeId) -> DomResult<NodeId> {
+		self.check_insertion(parent, Some(node), &self[node].node_type, Some(child), Some(child))?;
+		if node == child {
+			return Ok(child);
+		}
+		self.remove(node);
+		let previous_sibling = self[child].previous_sibling();
+		let next_sibling = self[child].next_sibling();
+		self.unlink(child);
+		self.link(parent, node, next_sibling);
+		self.notify(MutationRecord::ChildList {
+			target: parent,
+			added_nodes: vec![node],
+			removed_nodes: vec![child],
+			previous_sibling,
+			next_sibling,
+		});
+		Ok(child)
+	}
+
+	/// Sets an attribute of an element. Attribute names are lowercased, as
+	/// HTML attribute names aren't case-sensitive.
+	pub fn set_attribute(&mut self, id: NodeId, name: &str, value: &str) -> DomResult<()> {
+		let name = name.to_ascii_lowercase();
+		let NodeType::Element(ref mut element) = self.node_mut(id).node_type else {
+			return Err(DomError::InvalidNodeType);
+		};
+		let old_value = element.attributes.insert(name.clone(), value.to_string());
+		self.notify(MutationRecord::Attributes { target: id, name, old_value });
+		Ok(())
+	}
+
+	/// Removes an attribute of an element, returning its value if it had it.
+	pub fn remove_attribute(&mut self, id: NodeId, name: &str) -> DomResult<Option<String>> {
+		let name = name.to_ascii_lowercase();
+		let NodeType::Element(ref mut element) = self.node_mut(id).node_type else {
+			return Err(DomError::InvalidNodeType);
+		};
+		let old_value = element.attributes.remove(&name);
+		if old_value.is_some() {
+			self.notify(MutationRecord::Attributes { target: id, name, old_value: old_value.clone() });
+		}
+		Ok(old_value)
+	}
+
+	/// Replaces the text of a text or comment node, or replaces all the
+	/// children of an element with a single text node. Like the DOM's
+	/// `textContent`, this does nothing to documents and doctypes.
+	pub fn set_text_content(&mut self, id: NodeId, text: &str) {
+		match self.node_mut(id).node_type {
+			NodeType::Text(ref mut data) | NodeType::Comment(ref mut data) => {
+				let old_value = std::mem::replace(data, text.to_string());
+				self.notify(MutationRecord::CharacterData { target: id, old_value });
+			}
+			NodeType::Element(_) => {
+				let removed_nodes: Vec<NodeId> = self.children(id).collect();
+				for &child in &removed_nodes {
+					self.unlink(child);
+				}
+				let mut added_nodes = Vec::new();
+				if !text.is_empty() {
+					let text_node = self.create_node(dom::text(text.to_string()));
+					self.link(id, text_node, None);
+					added_nodes.push(text_node);
+				}
+				if !removed_nodes.is_empty() || !added_nodes.is_empty() {
+					self.notify(MutationRecord::ChildList {
+						target: id,
+						added_nodes,
+						removed_nodes,
+						previous_sibling: None,
+						next_sibling: None,
+					});
+				}
+			}
+			NodeType::Document(_) | NodeType::Doctype(_) => {}
+		}
+	}
+
+	/// Checks that a node of `node_type` can be inserted into `parent` before
+	/// `child`, following the DOM's pre-insertion validity rules. `node` is
+	/// the node itself, unless it isn't in the document yet, and `replacing`
+	/// is the child it would replace.
+	fn check_insertion(
+		&self,
+		parent: NodeId,
+		node: Option<NodeId>,
+		node_type: &NodeType,
+		child: Option<NodeId>,
+		replacing: Option<NodeId>,
+	) -> DomResult<()> {
+		let parent_is_document = match self[parent].node_type {
+			NodeType::Document(_) => true,
+			NodeType::Element(_) => false,
+			_ => return Err(DomError::HierarchyRequest),
+		};
+		if node.is_some_and(|node| self.is_inclusive_ancestor(node, parent)) {
+			return Err(DomError::HierarchyRequest);
+		}
+		if child.is_some_and(|child| self[child].parent() != Some(parent)) {
+			return Err(DomError::NotFound);
+		}
+		let other_children = || self.children(parent).filter(|&other| Some(other) != node && Some(other) != replacing);
+		let valid = match *node_type {
+			NodeType::Document(_) => false,
+			NodeType::Doctype(_) => {
+				parent_is_document && !other_children().any(|other| matches!(self[other].node_type, NodeType::Doctype(_)))
+			}
+			NodeType::Element(_) => {
+				!parent_is_document || !other_children().any(|other| self.element(other).is_some())
+			}
+			NodeType::Text(_) => !parent_is_document,
+			NodeType::Comment(_) => true,
+		};
+		match valid {
+			true => Ok(()),
+			false => Err(DomError::HierarchyRequest),
+		}
+	}
+
+	fn is_inclusive_ancestor(&self, ancestor: NodeId, mut id: NodeId) -> bool {
+		loop {
+			if id == ancestor {
+				return true;
+			}
+			match self[id].parent() {
+				Some(parent) => id = parent,
+				None => return false,
+			}
+		}
+	}
+
+	fn insert(&mut self, parent: NodeId, node: NodeId, child: Option<NodeId>) {
+		let previous_sibling = match child {
+			Some(child) => self[child].previous_sibling(),
+			None => self[parent].last_child(),
+		};
+		self.link(parent, node, child);
+		self.notify(MutationRecord::ChildList {
+			target: parent,
+			added_nodes: vec![node],
+			removed_nodes: Vec::new(),
+			previous_sibling,
+			next_sibling: child,
+		});
+	}
+
+	fn remove(&mut self, node: NodeId) {
+		let Some(parent) = self[node].parent() else {
+			return;
+		};
+		let previous_sibling = self[node].previous_sibling();
+		let next_sibling = self[node].next_sibling();
+		self.unlink(node);
+		self.notify(MutationRecord::ChildList {
+			target: parent,
+			added_nodes: Vec::new(),
+			removed_nodes: vec![node],
+			previous_sibling,
+			next_sibling,
+		});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::parse::html;
+
+	/// A document with `<div><p>one</p><p>two</p></div>` in its body, the
+	/// records of changes made to it, and the `div` and its `p`s.
+	fn document() -> (Document, Receiver<MutationRecord>, NodeId, NodeId, NodeId) {
+		let mut document = Document::from(html::parse("<div><p>one</p><p>two</p></div>".to_string()));
+		let records = document.observe();
+		let div = document.get_elements_by_tag_name(document.root(), "div")[0];
+		let (first, second) = match document.children(div).collect::<Vec<_>>()[..] {
+			[first, second] => (first, second),
+			_ => unreachable!(),
+		};
+		(document, records, div, first, second)
+	}
+
+	fn html(document: &Document) -> String {
+		document.to_node(document.root()).inner_html()
+	}
+
+	fn child_list(target: NodeId, added: &[NodeId], removed: &[NodeId], siblings: [Option<NodeId>; 2]) -> MutationRecord {
+		MutationRecord::ChildList {
+			target,
+			added_nodes: added.to_vec(),
+			removed_nodes: removed.to_vec(),
+			previous_sibling: siblings[0],
+			next_sibling: siblings[1],
+		}
+	}
+
+	#[test]
+	fn moving_a_node_reports_its_removal_and_insertion() {
+		let (mut document, records, div, first, second) = document();
+		assert_eq!(document.append_child(div, first), Ok(first));
+		assert_eq!(html(&document), "<html><head></head><body><div><p>two</p><p>one</p></div></body></html>");
+		assert_eq!(
+			records.try_iter().collect::<Vec<_>>(),
+			[child_list(div, &[], &[first], [None, Some(second)]), child_list(div, &[first], &[], [Some(second), None])]
+		);
+	}
+
+	#[test]
+	fn insert_before_reports_the_new_siblings() {
+		let (mut document, records, div, first, second) = document();
+		let comment = document.create_node(dom::comment(" c ".to_string()));
+		assert_eq!(document.insert_before(div, comment, Some(second)), Ok(comment));
+		assert_eq!(html(&document), "<html><head></head><body><div><p>one</p><!-- c --><p>two</p></div></body></html>");
+		assert_eq!(records.try_iter().collect::<Vec<_>>(), [child_list(div, &[comment], &[], [Some(first), Some(second)])]);
+	}
+
+	#[test]
+	fn remove_and_replace_child() {
+		let (mut document, records, div, first, second) = document();
+		assert_eq!(document.remove_child(div, first), Ok(first));
+		assert_eq!(records.try_recv(), Ok(child_list(div, &[], &[first], [None, Some(second)])));
+
+		let span = document.insert_tree(div, dom::element("span".to_string(), dom::AttrMap::new(), Vec::new())).unwrap();
+		assert_eq!(records.try_recv(), Ok(child_list(div, &[span], &[], [Some(second), None])));
+
+		assert_eq!(document.replace_child(div, first, second), Ok(second));
+		assert_eq!(records.try_recv(), Ok(child_list(div, &[first], &[second], [None, Some(span)])));
+		assert_eq!(html(&document), "<html><head></head><body><div><p>one</p><span></span></div></body></html>");
+		assert!(records.try_recv().is_err());
+	}
+
+	#[test]
+	fn attribute_changes_report_the_old_value() {
+		let (mut document, records, div, ..) = document();
+		assert_eq!(document.set_attribute(div, "ID", "a"), Ok(()));
+		assert_eq!(document.set_attribute(div, "id", "b"), Ok(()));
+		assert_eq!(document.remove_attribute(div, "id"), Ok(Some("b".to_string())));
+		assert_eq!(document.remove_attribute(div, "id"), Ok(None));
+		let attributes = |old_value: Option<&str>| MutationRecord::Attributes {
+			target: div,
+			name: "id".to_string(),
+			old_value: old_value.map(str::to_string),
+		};
+		assert_eq!(records.try_iter().collect::<Vec<_>>(), [attributes(None), attributes(Some("a")), attributes(Some("b"))]);
+	}
+
+	#[test]
+	fn set_text_content_replaces_text_or_children() {
+		let (mut document, records, div, first, second) = document();
+		let text = document.children(first).next().unwrap();
+		document.set_text_content(text, "uno");
+		assert_eq!(records.try_recv(), Ok(MutationRecord::CharacterData { target: text, old_value: "one".to_string() }));
+
+		document.set_text_content(div, "all");
+		let added = document.children(div).collect::<Vec<_>>();
+		assert_eq!(records.try_recv(), Ok(child_list(div, &added, &[first, second], [None, None])));
+		assert_eq!(html(&document), "<html><head></head><body><div>all</div></body></html>");
+
+		document.set_text_content(document.root(), "ignored");
+		assert!(records.try_recv().is_err());
+	}
+
+	#[test]
+	fn nodes_cant_be_inserted_into_themselves() {
+		let (mut document, records, div, first, _) = document();
+		let body = document[div].parent().unwrap();
+		let before = html(&document);
+		assert_eq!(document.append_child(div, div), Err(DomError::HierarchyRequest));
+		assert_eq!(document.append_child(first, body), Err(DomError::HierarchyRequest));
+		assert_eq!(document.insert_before(first, div, None), Err(DomError::HierarchyRequest));
+		assert_eq!(document.replace_child(div, body, first), Err(DomError::HierarchyRequest));
+		assert_eq!(html(&document), before);
+		assert!(records.try_recv().is_err());
+	}
+
+	#[test]
+	fn failed_changes_leave_the_document_alone() {
+		let (mut document, records, div, first, second) = document();
+		let root = document.root();
+		let text = document.children(first).next().unwrap();
+		let before = html(&document);
+		let unchanged = document.clone();
+
+		assert_eq!(document.remove_child(first, second), Err(DomError::NotFound));
+		assert_eq!(document.insert_before(div, text, Some(text)), Err(DomError::NotFound));
+		assert_eq!(document.replace_child(div, second, text), Err(DomError::NotFound));
+		assert_eq!(document.append_child(text, second), Err(DomError::HierarchyRequest));
+		assert_eq!(document.append_child(root, text), Err(DomError::HierarchyRequest));
+		assert_eq!(document.append_child(root, div), Err(DomError::HierarchyRequest));
+		assert_eq!(document.set_attribute(text, "id", "a"), Err(DomError::InvalidNodeType));
+		assert_eq!(document.remove_attribute(text, "id"), Err(DomError::InvalidNodeType));
+		let tree = dom::element("p".to_string(), dom::AttrMap::new(), vec![dom::text("three".to_string())]);
+		assert_eq!(document.insert_tree(root, tree), Err(DomError::HierarchyRequest));
+
+		assert_eq!(html(&document), before);
+		assert!(records.try_recv().is_err());
+		// No nodes were made for the tree that couldn't be inserted.
+		let comment = || dom::comment(String::new());
+		assert_eq!(document.create_node(comment()), unchanged.clone().create_node(comment()));
+	}
+}
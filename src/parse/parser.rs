@@ -1,46 +1,159 @@
+use std::rc::Rc;
+
+use crate::parse::{
+	error::{ParseError, ParseResult},
+	span::{Location, Span},
+};
+
 pub struct Parser {
 	position: usize,
+	line: usize,
+	column: usize,
+	file: Option<Rc<str>>,
+	origin: usize,
 	input: String,
 }
 
 impl Parser {
 	pub fn new(position: usize, input: String) -> Parser {
-		Parser { position, input }
+		let mut parser = Parser { position: 0, line: 1, column: 1, file: None, origin: 0, input };
+		while parser.position < position && parser.consume_char().is_some() {}
+		parser
+	}
+
+	pub fn with_file(mut self, file: &str) -> Parser {
+		self.file = Some(Rc::from(file));
+		self
+	}
+
+	/// Treats the current position as `origin`, for input that was taken from
+	/// the middle of a larger file.
+	pub fn with_origin(mut self, origin: Location) -> Parser {
+		self.line = origin.line;
+		self.column = origin.column;
+		self.origin = origin.offset - self.position;
+		self
 	}
-	
+
 	pub fn consume_whitespace(&mut self) {
 		self.consume_while(char::is_whitespace);
 	}
-	
+
 	pub fn consume_while<F>(&mut self, test: F) -> String where F: Fn(char) -> bool {
 		let mut result = String::new();
-		while !self.ended() && test(self.next_char()) {
-				result.push(self.consume_char());
+		while let Some(next) = self.next_char() {
+			if !test(next) {
+				break;
+			}
+			result.push(next);
+			self.advance(next);
 		}
 		result
 	}
-	
-	pub fn consume_char(&mut self) -> char {
-		let mut iterable = self.input[self.position..].char_indices();
-		let (_, current_char) = iterable.next().unwrap();
-    let (next_position, _) = iterable.next().unwrap_or((1, ' '));
-		self.position += next_position;
-    current_char
+
+	pub fn consume_char(&mut self) -> Option<char> {
+		let current_char = self.next_char()?;
+		self.advance(current_char);
+		Some(current_char)
+	}
+
+	fn advance(&mut self, current_char: char) {
+		self.position += current_char.len_utf8();
+		if current_char == '\n' {
+			self.line += 1;
+			self.column = 1;
+		} else {
+			self.column += 1;
+		}
+	}
+
+	pub fn expect_char(&mut self, expected: char) -> ParseResult<()> {
+		match self.next_char() {
+			Some(c) if c == expected => {
+				self.consume_char();
+				Ok(())
+			}
+			_ => Err(self.unexpected(&format!("'{}'", expected))),
+		}
 	}
-	
+
+	pub fn unexpected(&self, expected: &str) -> ParseError {
+		match self.next_char() {
+			Some(found) => ParseError::UnexpectedChar {
+				expected: expected.to_string(),
+				found,
+				span: self.span_from(self.location()),
+			},
+			None => ParseError::UnexpectedEof {
+				expected: expected.to_string(),
+				span: self.span_from(self.location()),
+			},
+		}
+	}
+
 	pub fn next_chars(&self, n: usize) -> String {
-		self.input[self.position .. self.position + n].to_string()
+		self.input[self.position..].chars().take(n).collect()
 	}
-	
-	pub fn next_char(&self) -> char {
-		self.input[self.position..].chars().next().unwrap()
+
+	pub fn next_char(&self) -> Option<char> {
+		self.input[self.position..].chars().next()
 	}
-	
+
 	pub fn starts_with(&self, test_str: &str) -> bool {
 		self.input[self.position..].starts_with(test_str)
 	}
-	
+
+	pub fn starts_with_ignore_ascii_case(&self, test_str: &str) -> bool {
+		self.input[self.position..]
+			.get(..test_str.len())
+			.is_some_and(|next| next.eq_ignore_ascii_case(test_str))
+	}
+
+	/// Appends more input, for input that arrives in chunks. The input that
+	/// has already been consumed is dropped, and counted in `origin` instead
+	/// so locations stay the same.
+	pub fn push_str(&mut self, chunk: &str) {
+		self.input.drain(..self.position);
+		self.origin += self.position;
+		self.position = 0;
+		self.input.push_str(chunk);
+	}
+
+	pub fn remaining(&self) -> &str {
+		&self.input[self.position..]
+	}
+
+	pub fn position(&self) -> usize {
+		self.position
+	}
+
+	pub fn location(&self) -> Location {
+		Location { offset: self.origin + self.position, line: self.line, column: self.column }
+	}
+
+	/// The span from `start` up to the current position.
+	pub fn span_from(&self, start: Location) -> Span {
+		Span::new(self.file.clone(), start, self.location())
+	}
+
 	pub fn ended(&self) -> bool {
 		self.position >= self.input.len()
 	}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn push_str_drops_consumed_input_but_keeps_locations() {
+		let mut parser = Parser::new(0, "ab\nc".to_string());
+		parser.consume_while(|_| true);
+		parser.push_str("de");
+		assert_eq!(parser.input, "de");
+		assert_eq!(parser.location(), Location { offset: 4, line: 2, column: 2 });
+		parser.consume_char();
+		assert_eq!(parser.location(), Location { offset: 5, line: 2, column: 3 });
+		assert_eq!(parser.remaining(), "e");
+	}
+}
@@ -0,0 +1,156 @@
+use crate::parse::{
+	css,
+	cssom::Selector,
+	dom::{Document, NodeId, QuirksMode},
+	error::ParseResult,
+	style,
+};
+
+/// Finding elements in a document. Everything here returns elements in
+/// document order, and searches only the descendants of the node it's
+/// given, not the node itself.
+impl Document {
+	/// The first element that matches any of the comma-separated
+	/// `selectors`, which are parsed like those in a stylesheet.
+	pub fn query_selector(&self, root: NodeId, selectors: &str) -> ParseResult<Option<NodeId>> {
+		let selectors = css::parse_selectors(selectors.to_string())?;
+		Ok(self.descendants(root).find(|&id| self.matches_any(id, &selectors)))
+	}
+
+	/// Every element that matches any of the comma-separated `selectors`.
+	pub fn query_selector_all(&self, root: NodeId, selectors: &str) -> ParseResult<Vec<NodeId>> {
+		let selectors = css::parse_selectors(selectors.to_string())?;
+		Ok(self.descendants(root).filter(|&id| self.matches_any(id, &selectors)).collect())
+	}
+
+	/// Whether `id` is an element that matches any of the comma-separated
+	/// `selectors`.
+	pub fn matches(&self, id: NodeId, selectors: &str) -> ParseResult<bool> {
+		let selectors = css::parse_selectors(selectors.to_string())?;
+		Ok(self.matches_any(id, &selectors))
+	}
+
+	fn matches_any(&self, id: NodeId, selectors: &[Selector]) -> bool {
+		selectors.iter().any(|selector| style::matches(self, id, selector))
+	}
+
+	/// The first element in the document whose `id` attribute is `element_id`.
+	pub fn get_element_by_id(&self, element_id: &str) -> Option<NodeId> {
+		self.descendants(self.root())
+			.find(|&id| self.element(id).is_some_and(|element| element.id().is_some_and(|id| id == element_id)))
+	}
+
+	/// Every element with the tag name `tag_name`, ignoring case, or every
+	/// element if it's `*`.
+	pub fn get_elements_by_tag_name(&self, root: NodeId, tag_name: &str) -> Vec<NodeId> {
+		self.descendants(root)
+			.filter(|&id| {
+				self.element(id)
+					.is_some_and(|element| tag_name == "*" || element.tag_name.eq_ignore_ascii_case(tag_name))
+			})
+			.collect()
+	}
+
+	/// Every element that has all of the space-separated `class_names`.
+	pub fn get_elements_by_class_name(&self, root: NodeId, class_names: &str) -> Vec<NodeId> {
+		let class_names: Vec<&str> = class_names.split_ascii_whitespace().collect();
+		if class_names.is_empty() {
+			return Vec::new();
+		}
+		let same_name = |a: &str, b: &str| match self.quirks_mode() {
+			QuirksMode::Quirks => a.eq_ignore_ascii_case(b),
+			_ => a == b,
+		};
+		self.descendants(root)
+			.filter(|&id| {
+				self.element(id).is_some_and(|element| {
+					let classes = element.classes();
+					class_names.iter().all(|name| classes.iter().any(|class| same_name(class, name)))
+				})
+			})
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::parse::html;
+
+	const BODY: &str = r#"<div id=outer class=box><p class=Intro>one</p><div id=Inner class="box small"><p>two</p><span><p class=intro>three</p></span></div></div>"#;
+
+	/// The document, and a way to find its elements by their id, by their
+	/// text for the `p`s, and otherwise by their tag name.
+	fn parse(doctype: &str) -> (Document, impl Fn(&str) -> NodeId) {
+		let document = Document::from(html::parse(format!("{}{}", doctype, BODY)));
+		let ids: Vec<(String, NodeId)> = document
+			.get_elements_by_tag_name(document.root(), "*")
+			.into_iter()
+			.map(|id| {
+				let element = document.element(id).unwrap();
+				match element.id() {
+					Some(element_id) => (element_id.clone(), id),
+					None if element.tag_name == "p" => (document.text_content(id), id),
+					None => (element.tag_name.clone(), id),
+				}
+			})
+			.collect();
+		let find = move |name: &str| ids.iter().find(|(other, _)| other == name).unwrap().1;
+		(document, find)
+	}
+
+	#[test]
+	fn results_are_in_document_order_without_the_context_node() {
+		let (document, find) = parse("<!DOCTYPE html>");
+		let outer = find("outer");
+		assert_eq!(
+			document.query_selector_all(outer, "p, div"),
+			Ok(vec![find("one"), find("Inner"), find("two"), find("three")])
+		);
+		assert_eq!(document.query_selector(outer, "p"), Ok(Some(find("one"))));
+		assert_eq!(document.query_selector(find("Inner"), "div"), Ok(None));
+		assert_eq!(document.query_selector(find("one"), "*"), Ok(None));
+		assert_eq!(document.matches(find("two"), "p"), Ok(true));
+		assert_eq!(document.matches(document.root(), "*"), Ok(false));
+	}
+
+	#[test]
+	fn compound_and_descendant_selectors() {
+		let (document, find) = parse("<!DOCTYPE html>");
+		let root = document.root();
+		assert_eq!(document.query_selector_all(root, "div.small.box"), Ok(vec![find("Inner")]));
+		assert_eq!(document.query_selector_all(root, "P.intro"), Ok(vec![find("three")]));
+		assert_eq!(document.query_selector_all(root, "#Inner p"), Ok(vec![find("two"), find("three")]));
+		assert_eq!(document.query_selector_all(root, "div  div\tp"), Ok(vec![find("two"), find("three")]));
+		assert_eq!(document.query_selector_all(root, ".box .box"), Ok(vec![find("Inner")]));
+		assert_eq!(document.matches(find("three"), "#outer span p.intro"), Ok(true));
+		assert_eq!(document.matches(find("one"), "span p"), Ok(false));
+		assert_eq!(document.matches(find("outer"), "div div"), Ok(false));
+	}
+
+	#[test]
+	fn invalid_selectors_are_errors() {
+		let (document, find) = parse("<!DOCTYPE html>");
+		let root = document.root();
+		assert!(document.query_selector(root, "").is_err());
+		assert!(document.query_selector_all(root, "div > p").is_err());
+		assert!(document.query_selector_all(root, "p,").is_err());
+		assert!(document.matches(find("one"), "p {").is_err());
+	}
+
+	#[test]
+	fn quirks_mode_ignores_the_case_of_classes_and_ids() {
+		let (document, find) = parse("<!DOCTYPE html>");
+		assert_eq!(document.quirks_mode(), QuirksMode::NoQuirks);
+		assert_eq!(document.query_selector_all(document.root(), "#inner, .INTRO"), Ok(vec![]));
+		assert_eq!(document.get_elements_by_class_name(document.root(), "intro"), [find("three")]);
+
+		let (document, find) = parse("");
+		assert_eq!(document.quirks_mode(), QuirksMode::Quirks);
+		assert_eq!(
+			document.query_selector_all(document.root(), "#inner, .INTRO"),
+			Ok(vec![find("one"), find("Inner"), find("three")])
+		);
+		assert_eq!(document.get_elements_by_class_name(document.root(), "intro"), [find("one"), find("three")]);
+	}
+}
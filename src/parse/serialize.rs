@@ -0,0 +1,154 @@
+use crate::parse::{
+	dom::{Node, NodeType},
+	html::is_void_element,
+};
+
+/// Elements whose text is serialized as it is, without escaping.
+const RAW_TEXT_ELEMENTS: [&str; 7] = ["style", "script", "xmp", "iframe", "noembed", "noframes", "plaintext"];
+
+impl Node {
+	/// Serializes the node's children as HTML, following the HTML fragment
+	/// serialization algorithm.
+	pub fn inner_html(&self) -> String {
+		let mut html = String::new();
+		let mut steps = Vec::new();
+		push_children(self, &mut steps, &mut html);
+		serialize(steps, &mut html);
+		html
+	}
+
+	/// Serializes the node itself as HTML, along with its children.
+	pub fn outer_html(&self) -> String {
+		let mut html = String::new();
+		serialize(vec![Step::Node(self, None)], &mut html);
+		html
+	}
+}
+
+/// Work still to do, last first: a node to serialize along with its
+/// parent's tag name, or the end tag of an element whose children are done.
+enum Step<'a> {
+	Node(&'a Node, Option<&'a str>),
+	EndTag(&'a str),
+}
+
+/// Queues `node`'s children to be serialized next.
+fn push_children<'a>(node: &'a Node, steps: &mut Vec<Step<'a>>, html: &mut String) {
+	let parent_tag = match node.node_type {
+		NodeType::Element(ref element) => Some(element.tag_name.as_str()),
+		_ => None,
+	};
+	// The parser drops a newline straight after these start tags, so one
+	// that's really part of the text needs another in front of it.
+	if matches!(parent_tag, Some("pre" | "textarea" | "listing")) {
+		if let Some(NodeType::Text(ref text)) = node.children.first().map(|child| &child.node_type) {
+			if text.starts_with('\n') {
+				html.push('\n');
+			}
+		}
+	}
+	steps.extend(node.children.iter().rev().map(|child| Step::Node(child, parent_tag)));
+}
+
+fn serialize(mut steps: Vec<Step>, html: &mut String) {
+	while let Some(step) = steps.pop() {
+		let (node, parent_tag) = match step {
+			Step::Node(node, parent_tag) => (node, parent_tag),
+			Step::EndTag(tag_name) => {
+				html.push_str("</");
+				html.push_str(tag_name);
+				html.push('>');
+				continue;
+			}
+		};
+		match node.node_type {
+			NodeType::Document(_) => push_children(node, &mut steps, html),
+			NodeType::Doctype(ref doctype) => {
+				html.push_str("<!DOCTYPE ");
+				html.push_str(&doctype.name);
+				html.push('>');
+			}
+			NodeType::Element(ref element) => {
+				html.push('<');
+				html.push_str(&element.tag_name);
+				for (name, value) in element.attributes.iter() {
+					html.push(' ');
+					html.push_str(name);
+					html.push_str("=\"");
+					escape(value, true, html);
+					html.push('"');
+				}
+				html.push('>');
+				if is_void_element(&element.tag_name) {
+					continue;
+				}
+				steps.push(Step::EndTag(&element.tag_name));
+				push_children(node, &mut steps, html);
+			}
+			NodeType::Text(ref text) => match parent_tag {
+				Some(tag) if RAW_TEXT_ELEMENTS.contains(&tag) => html.push_str(text),
+				_ => escape(text, false, html),
+			},
+			NodeType::Comment(ref comment) => {
+				html.push_str("<!--");
+				html.push_str(comment);
+				html.push_str("-->");
+			}
+		}
+	}
+}
+
+fn escape(text: &str, attribute_mode: bool, html: &mut String) {
+	for c in text.chars() {
+		match c {
+			'&' => html.push_str("&amp;"),
+			'\u{A0}' => html.push_str("&nbsp;"),
+			'<' => html.push_str("&lt;"),
+			'>' => html.push_str("&gt;"),
+			'"' if attribute_mode => html.push_str("&quot;"),
+			c => html.push(c),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::parse::html;
+
+	/// Parses `source`, serializes it and parses that again, checking the
+	/// second parse gives back the same tree, and returns the serialization.
+	fn round_trip(source: &str) -> String {
+		let html = html::parse(source.to_string()).inner_html();
+		assert_eq!(html::parse(html.clone()).inner_html(), html, "{:?} doesn't round trip", source);
+		html
+	}
+
+	#[test]
+	fn raw_text_is_not_escaped() {
+		let html = round_trip("<script>if (a < b && c > d) {}</script><style>a > b { color: red }</style>");
+		assert!(html.contains("<script>if (a < b && c > d) {}</script>"), "{}", html);
+		assert!(html.contains("<style>a > b { color: red }</style>"), "{}", html);
+	}
+
+	#[test]
+	fn text_is_escaped() {
+		let html = round_trip("<p>a &lt; b &amp;&amp; c &gt; d&nbsp;</p><textarea>&lt;/textarea&gt;</textarea>");
+		assert!(html.contains("<p>a &lt; b &amp;&amp; c &gt; d&nbsp;</p>"), "{}", html);
+		assert!(html.contains("<textarea>&lt;/textarea&gt;</textarea>"), "{}", html);
+	}
+
+	#[test]
+	fn leading_newlines_in_pre_and_textarea_are_kept() {
+		let html = round_trip("<pre>\n\nfirst</pre><textarea>\n\nsecond</textarea><listing>\n\nthird</listing>");
+		assert!(html.contains("<pre>\n\nfirst</pre>"), "{}", html);
+		assert!(html.contains("<textarea>\n\nsecond</textarea>"), "{}", html);
+		assert!(html.contains("<listing>\n\nthird</listing>"), "{}", html);
+	}
+
+	#[test]
+	fn attribute_values_are_escaped() {
+		let html = round_trip(r#"<a title='say "hi" & <wave>' href=x>link</a>"#);
+		assert!(html.contains(r#"title="say &quot;hi&quot; &amp; &lt;wave&gt;""#), "{}", html);
+		assert!(html.contains(r#"href="x""#), "{}", html);
+	}
+}
@@ -0,0 +1,54 @@
+use std::{fmt, rc::Rc};
+
+/// A point in the source text. `line` and `column` are 1-based and count
+/// characters; `offset` is the byte offset into the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// The region of source text a node, rule, selector or declaration was
+/// parsed from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Span {
+    pub file: Option<Rc<str>>,
+    pub start: Location,
+    pub end: Location,
+}
+
+impl Default for Location {
+    fn default() -> Location {
+        Location {
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+}
+
+impl Span {
+    pub fn new(file: Option<Rc<str>>, start: Location, end: Location) -> Span {
+        Span { file, start, end }
+    }
+
+    pub fn byte_range(&self) -> std::ops::Range<usize> {
+        self.start.offset..self.end.offset
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.file {
+            Some(ref file) => write!(f, "{}:{}", file, self.start),
+            None => write!(f, "{}", self.start),
+        }
+    }
+}
@@ -1,91 +1,149 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, mem};
 
 use crate::parse::{
-    dom::{ElementData, Node, NodeType::{Element,Text}},
+    css,
+    dom::{Document, ElementData, NodeData, NodeId, NodeType::{Element, Text}, QuirksMode},
     cssom::{Rule, Selector, SimpleSelector, Specificity, StyleSheet, Value},
+    whitespace,
 };
 
 type PropertyMap = HashMap<String, Value>;
 
 #[derive(Debug)]
 pub struct StyledNode<'a> {
-    pub node: &'a Node,
+    pub id: NodeId,
+    pub node: &'a NodeData,
     pub specified_values: PropertyMap,
     pub children: Vec<StyledNode<'a>>,
+    /// For text nodes, what's left to render after white space processing,
+    /// or `None` if that's nothing.
+    pub text: Option<String>,
+    /// The quirks mode of the document the node belongs to.
+    pub quirks_mode: QuirksMode,
+}
+
+/// Like a `dom::Node`, a styled tree is as deep as its document, so it's
+/// dropped from a stack of nodes rather than recursively.
+impl Drop for StyledNode<'_> {
+    fn drop(&mut self) {
+        let mut nodes = mem::take(&mut self.children);
+        while let Some(mut node) = nodes.pop() {
+            nodes.append(&mut node.children);
+        }
+    }
 }
 
 type MatchedRule<'a> = (Specificity, &'a Rule);
 
-pub fn style_tree<'a>(node: &'a Node, stylesheet: &'a StyleSheet, parent_node: Option<&'a Node>) -> StyledNode<'a> {
+/// Styles every node in `document` with the rules from `stylesheets`. Rules
+/// with equal specificity apply in order, so later stylesheets win.
+pub fn style_tree<'a>(document: &'a Document, stylesheets: &'a [StyleSheet]) -> StyledNode<'a> {
+    // Each node is styled after its children, which are the last styled
+    // nodes made.
+    let mut styled_nodes: Vec<StyledNode> = Vec::new();
+    let root = document.root();
+    for id in document.descendants_post_order(root).chain([root]) {
+        let children = styled_nodes.split_off(styled_nodes.len() - document.children(id).count());
+        styled_nodes.push(style_node(document, id, stylesheets, children));
+    }
+    let mut styled_node = styled_nodes.pop().expect("the root is always styled");
+    whitespace::collapse(&mut styled_node);
+    styled_node
+}
+
+fn style_node<'a>(document: &'a Document, id: NodeId, stylesheets: &'a [StyleSheet], children: Vec<StyledNode<'a>>) -> StyledNode<'a> {
     StyledNode {
-        node,
-        specified_values: determine_specified_values(node, stylesheet, parent_node),
-        children: node
-            .children
-            .iter()
-            .map(|child| style_tree(child, stylesheet, Some(node)))
-            .collect(),
+        id,
+        node: &document[id],
+        specified_values: determine_specified_values(document, id, stylesheets),
+        children,
+        text: None,
+        quirks_mode: document.quirks_mode(),
     }
 }
 
-fn determine_specified_values<'a>(node: &'a Node, stylesheet: &'a StyleSheet, parent_node: Option<&'a Node>) -> PropertyMap {
-	match node.node_type {
-			Element(ref element) => specified_values(element, stylesheet),
-			Text(_) => match parent_node {
-				Some(node) => match node.node_type {
-					Element(ref element) => specified_values(element, stylesheet),
-					_ => HashMap::new(),
-				}
-				None => HashMap::new()
-			},
-			_ => HashMap::new(),
-	}
+/// Text nodes take their values from their parent element.
+fn determine_specified_values(document: &Document, id: NodeId, stylesheets: &[StyleSheet]) -> PropertyMap {
+    let element = match document[id].node_type {
+        Element(_) => Some(id),
+        Text(_) => document[id].parent().filter(|&parent| document.element(parent).is_some()),
+        _ => None,
+    };
+    match element {
+        Some(element) => specified_values(document, element, stylesheets),
+        None => HashMap::new(),
+    }
 }
 
-fn specified_values(element: &ElementData, stylesheet: &StyleSheet) -> PropertyMap {
+/// Applies normal declarations and then `!important` ones. Within each, a
+/// `style` attribute overrides every matching rule.
+fn specified_values(document: &Document, id: NodeId, stylesheets: &[StyleSheet]) -> PropertyMap {
     let mut values = HashMap::new();
-    let mut rules = matching_rules(element, stylesheet);
+    let mut rules = matching_rules(document, id, stylesheets);
+    let inline_declarations = match document.element(id).and_then(|element| element.attributes.get("style")) {
+        Some(style) => css::parse_declarations(style.clone()),
+        None => Vec::new(),
+    };
 
-    rules.sort_by(|&(a, _), &(b, _)| a.cmp(&b));
-    for (_, rule) in rules {
-        for declaration in &rule.declarations {
-            values.insert(declaration.name.clone(), declaration.value.clone());
+    rules.sort_by_key(|&(specificity, _)| specificity);
+    for important in [false, true] {
+        let rule_declarations = rules.iter().flat_map(|(_, rule)| &rule.declarations);
+        for declaration in rule_declarations.chain(&inline_declarations) {
+            if declaration.important == important {
+                values.insert(declaration.name.clone(), declaration.value.clone());
+            }
         }
     }
     values
 }
 
-fn matching_rules<'a>(element: &ElementData, stylesheet: &'a StyleSheet) -> Vec<MatchedRule<'a>> {
-    stylesheet
-        .rules
+fn matching_rules<'a>(document: &Document, id: NodeId, stylesheets: &'a [StyleSheet]) -> Vec<MatchedRule<'a>> {
+    stylesheets
         .iter()
-        .filter_map(|rule| match_rule(element, rule))
+        .flat_map(|stylesheet| &stylesheet.rules)
+        .filter_map(|rule| match_rule(document, id, rule))
         .collect()
 }
 
-fn match_rule<'a>(element: &ElementData, rule: &'a Rule) -> Option<MatchedRule<'a>> {
+fn match_rule<'a>(document: &Document, id: NodeId, rule: &'a Rule) -> Option<MatchedRule<'a>> {
     rule.selectors
         .iter()
-        .find(|selector| matches(element, *selector))
+        .find(|selector| matches(document, id, selector))
         .map(|selector| (selector.specificity(), rule))
 }
 
-fn matches(element: &ElementData, selector: &Selector) -> bool {
+/// Whether `id` is an element that `selector` matches.
+pub fn matches(document: &Document, id: NodeId, selector: &Selector) -> bool {
+    let Some(element) = document.element(id) else {
+        return false;
+    };
+    let quirks_mode = document.quirks_mode();
     match *selector {
-        Selector::Simple(ref simple_selector) => matches_simple_selector(element, simple_selector),
+        Selector::Simple(ref simple_selector) => matches_simple_selector(element, simple_selector, quirks_mode),
+        Selector::Descendant { ref ancestor, ref subject, .. } => {
+            matches_simple_selector(element, subject, quirks_mode)
+                && document.ancestors(id).any(|ancestor_id| matches(document, ancestor_id, ancestor))
+        }
     }
 }
 
-fn matches_simple_selector(element: &ElementData, selector: &SimpleSelector) -> bool {
+/// Type selectors are case-insensitive, since HTML tag names are. Class and
+/// ID selectors are case-sensitive, except in quirks mode.
+fn matches_simple_selector(element: &ElementData, selector: &SimpleSelector, quirks_mode: QuirksMode) -> bool {
+    let same_name = |a: &str, b: &str| match quirks_mode {
+        QuirksMode::Quirks => a.eq_ignore_ascii_case(b),
+        _ => a == b,
+    };
+
     if selector
         .tag_name
         .iter()
-        .any(|name| element.tag_name != *name)
+        .any(|name| !element.tag_name.eq_ignore_ascii_case(name))
     {
         return false;
     }
 
-    if selector.id.iter().any(|id| element.id() != Some(id)) {
+    if selector.id.iter().any(|id| !element.id().is_some_and(|element_id| same_name(element_id, id))) {
         return false;
     }
 
@@ -93,10 +151,38 @@ fn matches_simple_selector(element: &ElementData, selector: &SimpleSelector) ->
     if selector
         .class
         .iter()
-        .any(|class| !classes.contains(&**class))
+        .any(|class| !classes.iter().any(|element_class| same_name(element_class, class)))
     {
         return false;
     }
 
     true
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::dom::{self, AttrMap};
+
+    #[test]
+    fn deep_trees_are_styled_and_dropped_without_recursing() {
+        const DEPTH: usize = 50_000;
+        let mut node = dom::text("x".to_string());
+        for _ in 0..DEPTH {
+            node = dom::element("span".to_string(), AttrMap::new(), vec![node]);
+        }
+        let document = Document::from(node);
+        let stylesheets = [css::parse("span { margin: 1px }".to_string())];
+
+        let mut depth = 0;
+        let style_tree = style_tree(&document, &stylesheets);
+        let mut styled_node = &style_tree;
+        while let Some(child) = styled_node.children.first() {
+            styled_node = child;
+            depth += 1;
+        }
+        assert_eq!(depth, DEPTH + 1);
+        assert_eq!(styled_node.text.as_deref(), Some("x"));
+        assert!(styled_node.specified_values.contains_key("margin"));
+    }
+}
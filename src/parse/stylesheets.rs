@@ -0,0 +1,192 @@
+use std::{
+	error::Error,
+	fmt, fs, io,
+	path::{Path, PathBuf},
+};
+
+use crate::parse::{
+	css,
+	cssom::StyleSheet,
+	dom::{Document, NodeId},
+	error::ParseError,
+};
+
+#[derive(Debug)]
+pub enum StylesheetError {
+	/// A `<link>`ed stylesheet that couldn't be read.
+	Io { path: PathBuf, error: io::Error },
+	/// Invalid CSS that was skipped while parsing a stylesheet.
+	Parse(ParseError),
+}
+
+impl fmt::Display for StylesheetError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			StylesheetError::Io { path, error } => {
+				write!(f, "couldn't read stylesheet {}: {}", path.display(), error)
+			}
+			StylesheetError::Parse(error) => write!(f, "ignored invalid CSS: {}", error),
+		}
+	}
+}
+
+impl Error for StylesheetError {}
+
+/// Collects the stylesheets of a parsed HTML document in document order:
+/// the contents of every `<style>` element, and every file referenced by a
+/// `<link rel="stylesheet">`, with `href` resolved relative to `html_path`.
+/// An `href` starting with `/` is taken to be relative to the directory of
+/// `html_path`, which stands in for the site's root. Alternate stylesheets
+/// and those whose `media` isn't for screens are left out. Stylesheets that
+/// can't be read are left out and reported in the returned errors.
+pub fn load(document: &Document, html_path: &Path) -> (Vec<StyleSheet>, Vec<StylesheetError>) {
+	let mut loader = Loader { document, html_path, stylesheets: Vec::new(), errors: Vec::new() };
+	for id in document.descendants(document.root()) {
+		loader.visit(id);
+	}
+	(loader.stylesheets, loader.errors)
+}
+
+struct Loader<'a> {
+	document: &'a Document,
+	html_path: &'a Path,
+	stylesheets: Vec<StyleSheet>,
+	errors: Vec<StylesheetError>,
+}
+
+impl Loader<'_> {
+	fn visit(&mut self, id: NodeId) {
+		if let Some(element) = self.document.element(id) {
+			if !is_for_screens(element.attributes.get("media")) {
+				return;
+			}
+			match element.tag_name.as_str() {
+				"style" => self.load_style_element(id),
+				"link" if is_stylesheet_link(element.attributes.get("rel")) => {
+					if let Some(href) = element.attributes.get("href") {
+						self.load_linked(href);
+					}
+				}
+				_ => {}
+			}
+		}
+	}
+
+	fn load_style_element(&mut self, style: NodeId) {
+		let origin = match self.document[style].first_child() {
+			Some(text) => self.document[text].span.start,
+			None => return,
+		};
+		let file_name = self.html_path.to_string_lossy();
+		let (stylesheet, errors) = css::parse_at(self.document.text_content(style), Some(&file_name), origin);
+		self.add(stylesheet, errors);
+	}
+
+	fn load_linked(&mut self, href: &str) {
+		let href = href.trim_start_matches('/');
+		let path = match self.html_path.parent() {
+			Some(directory) => directory.join(href),
+			None => PathBuf::from(href),
+		};
+		match fs::read_to_string(&path) {
+			Ok(source) => {
+				let (stylesheet, errors) = css::parse_with_errors(source, Some(&path.to_string_lossy()));
+				self.add(stylesheet, errors);
+			}
+			Err(error) => self.errors.push(StylesheetError::Io { path, error }),
+		}
+	}
+
+	fn add(&mut self, stylesheet: StyleSheet, errors: Vec<ParseError>) {
+		self.stylesheets.push(stylesheet);
+		self.errors.extend(errors.into_iter().map(StylesheetError::Parse));
+	}
+}
+
+/// `rel` is a space-separated, case-insensitive list of link types. An
+/// `alternate` stylesheet is one the user would have to pick, so it
+/// doesn't count.
+fn is_stylesheet_link(rel: Option<&String>) -> bool {
+	let Some(rel) = rel else {
+		return false;
+	};
+	let has = |name: &str| rel.split_ascii_whitespace().any(|link_type| link_type.eq_ignore_ascii_case(name));
+	has("stylesheet") && !has("alternate")
+}
+
+/// Whether a `media` attribute's comma-separated list of media queries
+/// includes screens. Only bare media types are understood, so a query with
+/// features, like `screen and (min-width: 40em)`, never matches.
+fn is_for_screens(media: Option<&String>) -> bool {
+	let Some(media) = media else {
+		return true;
+	};
+	media.trim().is_empty()
+		|| media.split(',').any(|query| {
+			let query = query.trim().to_ascii_lowercase();
+			let media_type = query.strip_prefix("only ").map_or(query.as_str(), str::trim_start);
+			matches!(media_type, "all" | "screen")
+		})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::parse::html;
+
+	/// Loads the stylesheets of `source`, as if it were a page in a
+	/// directory of its own along with `files`.
+	fn load_page(name: &str, source: &str, files: &[(&str, &str)]) -> (Vec<String>, Vec<StylesheetError>) {
+		let directory = std::env::temp_dir().join(format!("stylesheets-{}-{}", std::process::id(), name));
+		fs::create_dir_all(&directory).unwrap();
+		for (file_name, contents) in files {
+			fs::write(directory.join(file_name), contents).unwrap();
+		}
+		let document = Document::from(html::parse(source.to_string()));
+		let (stylesheets, errors) = load(&document, &directory.join("page.html"));
+		fs::remove_dir_all(&directory).unwrap();
+		(stylesheets.iter().map(StyleSheet::minified).collect(), errors)
+	}
+
+	#[test]
+	fn style_elements_and_links_in_document_order() {
+		let (stylesheets, errors) = load_page(
+			"order",
+			"<link rel=stylesheet href=a.css><style>p { margin: 2px }</style><body><link rel='Icon STYLESHEET' href=/b.css>",
+			&[("a.css", "a { margin: 1px }"), ("b.css", "b { margin: 3px }")],
+		);
+		assert!(errors.is_empty(), "{:?}", errors);
+		assert_eq!(stylesheets, ["a{margin:1px}", "p{margin:2px}", "b{margin:3px}"]);
+	}
+
+	#[test]
+	fn alternate_and_non_screen_stylesheets_are_left_out() {
+		let (stylesheets, errors) = load_page(
+			"skipped",
+			"<link rel='alternate stylesheet' href=a.css><link rel=stylesheet media=print href=a.css>\
+			 <style media='print, ONLY screen'>p { margin: 1px }</style>\
+			 <style media='screen and (min-width: 1px)'>p { margin: 2px }</style>\
+			 <style media=''>p { margin: 3px }</style>",
+			&[("a.css", "a { margin: 1px }")],
+		);
+		assert!(errors.is_empty(), "{:?}", errors);
+		assert_eq!(stylesheets, ["p{margin:1px}", "p{margin:3px}"]);
+	}
+
+	#[test]
+	fn missing_files_and_invalid_css_are_reported() {
+		let (stylesheets, errors) = load_page(
+			"errors",
+			"<link rel=stylesheet href=missing.css><link rel=stylesheet href=a.css>",
+			&[("a.css", "a { margin: 1px; color: #zz }")],
+		);
+		assert_eq!(stylesheets, ["a{margin:1px}"]);
+		match &errors[..] {
+			[StylesheetError::Io { path, .. }, StylesheetError::Parse(ParseError::InvalidColor { span, .. })] => {
+				assert!(path.ends_with("missing.css"));
+				assert!(span.file.as_deref().is_some_and(|file| file.ends_with("a.css")), "{:?}", span);
+			}
+			_ => panic!("unexpected errors {:?}", errors),
+		}
+	}
+}
@@ -0,0 +1,1543 @@
+use std::collections::{HashSet, VecDeque};
+
+use crate::parse::{
+	encoding, entities,
+	error::ParseError,
+	parser::Parser,
+	span::{Location, Span},
+};
+
+/// A token emitted by the tokenizer, following the WHATWG HTML tokenization
+/// algorithm.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+	Doctype(DoctypeToken),
+	StartTag(TagToken),
+	EndTag(TagToken),
+	Comment(String),
+	Character(char),
+	EndOfFile,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TagToken {
+	pub name: String,
+	pub attributes: Vec<(String, String)>,
+	pub self_closing: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DoctypeToken {
+	pub name: Option<String>,
+	pub public_id: Option<String>,
+	pub system_id: Option<String>,
+	pub force_quirks: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+	Data,
+	Rcdata,
+	Rawtext,
+	ScriptData,
+	Plaintext,
+	TagOpen,
+	EndTagOpen,
+	TagName,
+	RcdataLessThanSign,
+	RcdataEndTagOpen,
+	RcdataEndTagName,
+	RawtextLessThanSign,
+	RawtextEndTagOpen,
+	RawtextEndTagName,
+	ScriptDataLessThanSign,
+	ScriptDataEndTagOpen,
+	ScriptDataEndTagName,
+	ScriptDataEscapeStart,
+	ScriptDataEscapeStartDash,
+	ScriptDataEscaped,
+	ScriptDataEscapedDash,
+	ScriptDataEscapedDashDash,
+	ScriptDataEscapedLessThanSign,
+	ScriptDataEscapedEndTagOpen,
+	ScriptDataEscapedEndTagName,
+	ScriptDataDoubleEscapeStart,
+	ScriptDataDoubleEscaped,
+	ScriptDataDoubleEscapedDash,
+	ScriptDataDoubleEscapedDashDash,
+	ScriptDataDoubleEscapedLessThanSign,
+	ScriptDataDoubleEscapeEnd,
+	BeforeAttributeName,
+	AttributeName,
+	AfterAttributeName,
+	BeforeAttributeValue,
+	AttributeValueDoubleQuoted,
+	AttributeValueSingleQuoted,
+	AttributeValueUnquoted,
+	AfterAttributeValueQuoted,
+	SelfClosingStartTag,
+	BogusComment,
+	MarkupDeclarationOpen,
+	CommentStart,
+	CommentStartDash,
+	Comment,
+	CommentLessThanSign,
+	CommentLessThanSignBang,
+	CommentLessThanSignBangDash,
+	CommentLessThanSignBangDashDash,
+	CommentEndDash,
+	CommentEnd,
+	CommentEndBang,
+	Doctype,
+	BeforeDoctypeName,
+	DoctypeName,
+	AfterDoctypeName,
+	AfterDoctypePublicKeyword,
+	BeforeDoctypePublicIdentifier,
+	DoctypePublicIdentifierDoubleQuoted,
+	DoctypePublicIdentifierSingleQuoted,
+	AfterDoctypePublicIdentifier,
+	BetweenDoctypePublicAndSystemIdentifiers,
+	AfterDoctypeSystemKeyword,
+	BeforeDoctypeSystemIdentifier,
+	DoctypeSystemIdentifierDoubleQuoted,
+	DoctypeSystemIdentifierSingleQuoted,
+	AfterDoctypeSystemIdentifier,
+	BogusDoctype,
+	CdataSection,
+	CdataSectionBracket,
+	CdataSectionEnd,
+	CharacterReference,
+	NamedCharacterReference,
+	AmbiguousAmpersand,
+	NumericCharacterReference,
+	HexadecimalCharacterReferenceStart,
+	DecimalCharacterReferenceStart,
+	HexadecimalCharacterReference,
+	DecimalCharacterReference,
+	NumericCharacterReferenceEnd,
+}
+
+pub struct Tokenizer {
+	parser: Parser,
+	state: State,
+	return_state: State,
+	current_char: Option<char>,
+	current_start: Location,
+	reconsume: bool,
+	token_start: Location,
+	current_tag: TagToken,
+	current_tag_is_end: bool,
+	current_comment: String,
+	current_doctype: DoctypeToken,
+	temporary_buffer: String,
+	character_reference_code: u32,
+	character_reference_start: Location,
+	last_start_tag_name: Option<String>,
+	in_foreign_content: bool,
+	pending: VecDeque<(Token, Span)>,
+	input_finished: bool,
+	ended_with_cr: bool,
+	emitted_eof: bool,
+	errors: Vec<ParseError>,
+}
+
+pub fn is_html_whitespace(c: char) -> bool {
+	matches!(c, '\t' | '\n' | '\u{0C}' | ' ')
+}
+
+/// Newlines are normalized before tokenizing, as the spec's input stream
+/// preprocessing requires.
+fn normalize_newlines(input: String) -> String {
+	if input.contains('\r') {
+		input.replace("\r\n", "\n").replace('\r', "\n")
+	} else {
+		input
+	}
+}
+
+impl Tokenizer {
+	pub fn new(input: String) -> Tokenizer {
+		Tokenizer::from_parser(Parser::new(0, normalize_newlines(input)))
+	}
+
+	pub fn with_file(input: String, file_name: &str) -> Tokenizer {
+		Tokenizer::from_parser(Parser::new(0, normalize_newlines(input)).with_file(file_name))
+	}
+
+	/// A tokenizer for input that arrives in chunks through `push_str`.
+	/// Until `finish` is called, running out of input pauses tokenization
+	/// instead of ending it.
+	pub fn streaming(file_name: Option<&str>) -> Tokenizer {
+		let parser = match file_name {
+			Some(file_name) => Parser::new(0, String::new()).with_file(file_name),
+			None => Parser::new(0, String::new()),
+		};
+		Tokenizer { input_finished: false, ..Tokenizer::from_parser(parser) }
+	}
+
+	fn from_parser(parser: Parser) -> Tokenizer {
+		let start = parser.location();
+		Tokenizer {
+			parser,
+			state: State::Data,
+			return_state: State::Data,
+			current_char: None,
+			current_start: start,
+			reconsume: false,
+			token_start: start,
+			current_tag: TagToken::default(),
+			current_tag_is_end: false,
+			current_comment: String::new(),
+			current_doctype: DoctypeToken::default(),
+			temporary_buffer: String::new(),
+			character_reference_code: 0,
+			character_reference_start: start,
+			last_start_tag_name: None,
+			in_foreign_content: false,
+			pending: VecDeque::new(),
+			input_finished: true,
+			ended_with_cr: false,
+			emitted_eof: false,
+			errors: Vec::new(),
+		}
+	}
+
+	/// Switches state from outside the tokenizer. Tree construction uses this
+	/// for elements whose contents are RCDATA, RAWTEXT or script data.
+	pub fn set_state(&mut self, state: State) {
+		self.state = state;
+	}
+
+	/// Tells the tokenizer whether the adjusted current node is outside the
+	/// HTML namespace, which is the only place CDATA sections are allowed.
+	pub fn set_in_foreign_content(&mut self, in_foreign_content: bool) {
+		self.in_foreign_content = in_foreign_content;
+	}
+
+	pub fn push_str(&mut self, chunk: &str) {
+		if chunk.is_empty() {
+			return;
+		}
+		// A CRLF split across chunks is still one newline.
+		let chunk = match self.ended_with_cr {
+			true => chunk.strip_prefix('\n').unwrap_or(chunk),
+			false => chunk,
+		};
+		self.ended_with_cr = chunk.ends_with('\r');
+		self.parser.push_str(&normalize_newlines(chunk.to_string()));
+	}
+
+	/// Marks the end of the input, after which the tokenizer emits the rest of
+	/// its tokens and `EndOfFile`.
+	pub fn finish(&mut self) {
+		self.input_finished = true;
+	}
+
+	/// Parse errors encountered so far. None of them stop tokenization.
+	pub fn errors(&self) -> &[ParseError] {
+		&self.errors
+	}
+
+	pub fn take_errors(&mut self) -> Vec<ParseError> {
+		std::mem::take(&mut self.errors)
+	}
+
+	/// The next token, or `None` once `EndOfFile` has been returned or while
+	/// a streaming tokenizer is waiting for more input.
+	pub fn next_token(&mut self) -> Option<(Token, Span)> {
+		while self.pending.is_empty() {
+			if self.emitted_eof || self.needs_input() {
+				return None;
+			}
+			self.step();
+		}
+		self.pending.pop_front()
+	}
+
+	/// Whether the current state needs to look further ahead than the input
+	/// received so far.
+	fn needs_input(&self) -> bool {
+		if self.input_finished {
+			return false;
+		}
+		let lookahead = match self.state {
+			State::MarkupDeclarationOpen => "[CDATA[".len(),
+			// The longest name, and the character after it.
+			State::NamedCharacterReference => entities::LONGEST_NAME + 1,
+			State::AfterDoctypeName => "PUBLIC".len(),
+			State::NumericCharacterReferenceEnd => 0,
+			_ => 1,
+		};
+		let available = usize::from(self.reconsume) + self.parser.remaining().chars().take(lookahead).count();
+		available < lookahead
+	}
+
+	fn consume(&mut self) -> Option<char> {
+		if self.reconsume {
+			self.reconsume = false;
+		} else {
+			self.current_start = self.parser.location();
+			self.current_char = self.parser.consume_char();
+		}
+		self.current_char
+	}
+
+	fn reconsume_in(&mut self, state: State) {
+		self.reconsume = true;
+		self.state = state;
+	}
+
+	fn error(&mut self, code: &'static str) {
+		let span = self.parser.span_from(self.current_start);
+		self.errors.push(ParseError::Syntax { code, span });
+	}
+
+	fn emit(&mut self, token: Token, start: Location) {
+		if let Token::EndOfFile = token {
+			self.emitted_eof = true;
+		}
+		let span = self.parser.span_from(start);
+		self.pending.push_back((token, span));
+	}
+
+	fn emit_char(&mut self, c: char) {
+		self.emit(Token::Character(c), self.current_start);
+	}
+
+	fn emit_str(&mut self, s: &str) {
+		for c in s.chars() {
+			self.emit_char(c);
+		}
+	}
+
+	fn emit_eof(&mut self) {
+		self.emit(Token::EndOfFile, self.current_start);
+	}
+
+	fn emit_temporary_buffer(&mut self) {
+		let buffer = std::mem::take(&mut self.temporary_buffer);
+		self.emit_str(&buffer);
+	}
+
+	fn begin_tag(&mut self, is_end: bool) {
+		self.current_tag = TagToken::default();
+		self.current_tag_is_end = is_end;
+	}
+
+	fn emit_current_tag(&mut self) {
+		let mut tag = std::mem::take(&mut self.current_tag);
+		let mut seen = HashSet::new();
+		tag.attributes.retain(|(name, _)| seen.insert(name.clone()));
+		if self.current_tag_is_end {
+			if !tag.attributes.is_empty() {
+				self.error("end-tag-with-attributes");
+			}
+			if tag.self_closing {
+				self.error("end-tag-with-trailing-solidus");
+			}
+			self.emit(Token::EndTag(tag), self.token_start);
+		} else {
+			self.last_start_tag_name = Some(tag.name.clone());
+			self.emit(Token::StartTag(tag), self.token_start);
+		}
+	}
+
+	fn emit_current_comment(&mut self) {
+		let comment = std::mem::take(&mut self.current_comment);
+		self.emit(Token::Comment(comment), self.token_start);
+	}
+
+	fn emit_current_doctype(&mut self) {
+		let doctype = std::mem::take(&mut self.current_doctype);
+		self.emit(Token::Doctype(doctype), self.token_start);
+	}
+
+	fn emit_doctype_with_force_quirks(&mut self) {
+		self.current_doctype.force_quirks = true;
+		self.emit_current_doctype();
+	}
+
+	fn start_attribute(&mut self, name: String) {
+		self.current_tag.attributes.push((name, String::new()));
+	}
+
+	/// Reports an attribute that repeats an earlier one on the same tag. The
+	/// duplicate is dropped when the tag is emitted.
+	fn finish_attribute_name(&mut self) {
+		if let Some(((name, _), earlier)) = self.current_tag.attributes.split_last() {
+			if earlier.iter().any(|(earlier_name, _)| earlier_name == name) {
+				self.error("duplicate-attribute");
+			}
+		}
+	}
+
+	fn push_to_attribute_name(&mut self, c: char) {
+		if let Some((name, _)) = self.current_tag.attributes.last_mut() {
+			name.push(c);
+		}
+	}
+
+	fn push_to_attribute_value(&mut self, c: char) {
+		if let Some((_, value)) = self.current_tag.attributes.last_mut() {
+			value.push(c);
+		}
+	}
+
+	fn is_appropriate_end_tag(&self) -> bool {
+		self.last_start_tag_name.as_deref() == Some(self.current_tag.name.as_str())
+	}
+
+	/// Consumes `keyword` if the input continues with it after the current
+	/// character, which has already been checked against its first letter.
+	fn consume_keyword_rest(&mut self, keyword: &str) -> bool {
+		let rest = &keyword[1..];
+		if !self.parser.starts_with_ignore_ascii_case(rest) {
+			return false;
+		}
+		for _ in rest.chars() {
+			self.parser.consume_char();
+		}
+		true
+	}
+
+	fn step(&mut self) {
+		match self.state {
+			State::MarkupDeclarationOpen => return self.markup_declaration_open(),
+			State::NamedCharacterReference => return self.named_character_reference(),
+			State::NumericCharacterReferenceEnd => return self.numeric_character_reference_end(),
+			_ => {}
+		}
+
+		let next = self.consume();
+		match self.state {
+			State::Data => match next {
+				Some('&') => self.begin_character_reference(),
+				Some('<') => {
+					self.token_start = self.current_start;
+					self.state = State::TagOpen;
+				}
+				Some('\0') => {
+					self.error("unexpected-null-character");
+					self.emit_char('\0');
+				}
+				Some(c) => self.emit_char(c),
+				None => self.emit_eof(),
+			},
+			State::Rcdata => match next {
+				Some('&') => self.begin_character_reference(),
+				Some('<') => self.enter_less_than_sign(State::RcdataLessThanSign),
+				Some(c) => self.emit_text_char(c),
+				None => self.emit_eof(),
+			},
+			State::Rawtext => match next {
+				Some('<') => self.enter_less_than_sign(State::RawtextLessThanSign),
+				Some(c) => self.emit_text_char(c),
+				None => self.emit_eof(),
+			},
+			State::ScriptData => match next {
+				Some('<') => self.enter_less_than_sign(State::ScriptDataLessThanSign),
+				Some(c) => self.emit_text_char(c),
+				None => self.emit_eof(),
+			},
+			State::Plaintext => match next {
+				Some(c) => self.emit_text_char(c),
+				None => self.emit_eof(),
+			},
+			State::TagOpen => match next {
+				Some('!') => self.state = State::MarkupDeclarationOpen,
+				Some('/') => self.state = State::EndTagOpen,
+				Some(c) if c.is_ascii_alphabetic() => {
+					self.begin_tag(false);
+					self.reconsume_in(State::TagName);
+				}
+				Some('?') => {
+					self.error("unexpected-question-mark-instead-of-tag-name");
+					self.current_comment.clear();
+					self.reconsume_in(State::BogusComment);
+				}
+				Some(_) => {
+					self.error("invalid-first-character-of-tag-name");
+					self.emit(Token::Character('<'), self.token_start);
+					self.reconsume_in(State::Data);
+				}
+				None => {
+					self.error("eof-before-tag-name");
+					self.emit(Token::Character('<'), self.token_start);
+					self.emit_eof();
+				}
+			},
+			State::EndTagOpen => match next {
+				Some(c) if c.is_ascii_alphabetic() => {
+					self.begin_tag(true);
+					self.reconsume_in(State::TagName);
+				}
+				Some('>') => {
+					self.error("missing-end-tag-name");
+					self.state = State::Data;
+				}
+				Some(_) => {
+					self.error("invalid-first-character-of-tag-name");
+					self.current_comment.clear();
+					self.reconsume_in(State::BogusComment);
+				}
+				None => {
+					self.error("eof-before-tag-name");
+					self.emit(Token::Character('<'), self.token_start);
+					self.emit(Token::Character('/'), self.token_start);
+					self.emit_eof();
+				}
+			},
+			State::TagName => match next {
+				Some(c) if is_html_whitespace(c) => self.state = State::BeforeAttributeName,
+				Some('/') => self.state = State::SelfClosingStartTag,
+				Some('>') => {
+					self.state = State::Data;
+					self.emit_current_tag();
+				}
+				Some('\0') => {
+					self.error("unexpected-null-character");
+					self.current_tag.name.push('\u{FFFD}');
+				}
+				Some(c) => self.current_tag.name.push(c.to_ascii_lowercase()),
+				None => {
+					self.error("eof-in-tag");
+					self.emit_eof();
+				}
+			},
+			State::RcdataLessThanSign => self.less_than_sign_in_text(next, State::Rcdata, State::RcdataEndTagOpen),
+			State::RcdataEndTagOpen => self.end_tag_open_in_text(next, State::Rcdata, State::RcdataEndTagName),
+			State::RcdataEndTagName => self.end_tag_name_in_text(next, State::Rcdata),
+			State::RawtextLessThanSign => self.less_than_sign_in_text(next, State::Rawtext, State::RawtextEndTagOpen),
+			State::RawtextEndTagOpen => self.end_tag_open_in_text(next, State::Rawtext, State::RawtextEndTagName),
+			State::RawtextEndTagName => self.end_tag_name_in_text(next, State::Rawtext),
+			State::ScriptDataLessThanSign => match next {
+				Some('!') => {
+					self.state = State::ScriptDataEscapeStart;
+					self.emit_str("<!");
+				}
+				_ => self.less_than_sign_in_text(next, State::ScriptData, State::ScriptDataEndTagOpen),
+			},
+			State::ScriptDataEndTagOpen => self.end_tag_open_in_text(next, State::ScriptData, State::ScriptDataEndTagName),
+			State::ScriptDataEndTagName => self.end_tag_name_in_text(next, State::ScriptData),
+			State::ScriptDataEscapeStart => match next {
+				Some('-') => {
+					self.state = State::ScriptDataEscapeStartDash;
+					self.emit_char('-');
+				}
+				_ => self.reconsume_in(State::ScriptData),
+			},
+			State::ScriptDataEscapeStartDash => match next {
+				Some('-') => {
+					self.state = State::ScriptDataEscapedDashDash;
+					self.emit_char('-');
+				}
+				_ => self.reconsume_in(State::ScriptData),
+			},
+			State::ScriptDataEscaped => match next {
+				Some('-') => {
+					self.state = State::ScriptDataEscapedDash;
+					self.emit_char('-');
+				}
+				Some('<') => self.enter_less_than_sign(State::ScriptDataEscapedLessThanSign),
+				Some(c) => self.emit_text_char(c),
+				None => self.eof_in_script_comment(),
+			},
+			State::ScriptDataEscapedDash => match next {
+				Some('-') => {
+					self.state = State::ScriptDataEscapedDashDash;
+					self.emit_char('-');
+				}
+				Some('<') => self.enter_less_than_sign(State::ScriptDataEscapedLessThanSign),
+				Some(c) => {
+					self.state = State::ScriptDataEscaped;
+					self.emit_text_char(c);
+				}
+				None => self.eof_in_script_comment(),
+			},
+			State::ScriptDataEscapedDashDash => match next {
+				Some('-') => self.emit_char('-'),
+				Some('<') => self.enter_less_than_sign(State::ScriptDataEscapedLessThanSign),
+				Some('>') => {
+					self.state = State::ScriptData;
+					self.emit_char('>');
+				}
+				Some(c) => {
+					self.state = State::ScriptDataEscaped;
+					self.emit_text_char(c);
+				}
+				None => self.eof_in_script_comment(),
+			},
+			State::ScriptDataEscapedLessThanSign => match next {
+				Some('/') => {
+					self.temporary_buffer.clear();
+					self.state = State::ScriptDataEscapedEndTagOpen;
+				}
+				Some(c) if c.is_ascii_alphabetic() => {
+					self.temporary_buffer.clear();
+					self.emit_char('<');
+					self.reconsume_in(State::ScriptDataDoubleEscapeStart);
+				}
+				_ => {
+					self.emit_char('<');
+					self.reconsume_in(State::ScriptDataEscaped);
+				}
+			},
+			State::ScriptDataEscapedEndTagOpen => {
+				self.end_tag_open_in_text(next, State::ScriptDataEscaped, State::ScriptDataEscapedEndTagName)
+			}
+			State::ScriptDataEscapedEndTagName => self.end_tag_name_in_text(next, State::ScriptDataEscaped),
+			State::ScriptDataDoubleEscapeStart => {
+				self.double_escape_boundary(next, State::ScriptDataDoubleEscaped, State::ScriptDataEscaped)
+			}
+			State::ScriptDataDoubleEscaped => match next {
+				Some('-') => {
+					self.state = State::ScriptDataDoubleEscapedDash;
+					self.emit_char('-');
+				}
+				Some('<') => {
+					self.state = State::ScriptDataDoubleEscapedLessThanSign;
+					self.emit_char('<');
+				}
+				Some(c) => self.emit_text_char(c),
+				None => self.eof_in_script_comment(),
+			},
+			State::ScriptDataDoubleEscapedDash => match next {
+				Some('-') => {
+					self.state = State::ScriptDataDoubleEscapedDashDash;
+					self.emit_char('-');
+				}
+				Some('<') => {
+					self.state = State::ScriptDataDoubleEscapedLessThanSign;
+					self.emit_char('<');
+				}
+				Some(c) => {
+					self.state = State::ScriptDataDoubleEscaped;
+					self.emit_text_char(c);
+				}
+				None => self.eof_in_script_comment(),
+			},
+			State::ScriptDataDoubleEscapedDashDash => match next {
+				Some('-') => self.emit_char('-'),
+				Some('<') => {
+					self.state = State::ScriptDataDoubleEscapedLessThanSign;
+					self.emit_char('<');
+				}
+				Some('>') => {
+					self.state = State::ScriptData;
+					self.emit_char('>');
+				}
+				Some(c) => {
+					self.state = State::ScriptDataDoubleEscaped;
+					self.emit_text_char(c);
+				}
+				None => self.eof_in_script_comment(),
+			},
+			State::ScriptDataDoubleEscapedLessThanSign => match next {
+				Some('/') => {
+					self.temporary_buffer.clear();
+					self.state = State::ScriptDataDoubleEscapeEnd;
+					self.emit_char('/');
+				}
+				_ => self.reconsume_in(State::ScriptDataDoubleEscaped),
+			},
+			State::ScriptDataDoubleEscapeEnd => {
+				self.double_escape_boundary(next, State::ScriptDataEscaped, State::ScriptDataDoubleEscaped)
+			}
+			State::BeforeAttributeName => match next {
+				Some(c) if is_html_whitespace(c) => {}
+				Some('/' | '>') | None => self.reconsume_in(State::AfterAttributeName),
+				Some('=') => {
+					self.error("unexpected-equals-sign-before-attribute-name");
+					self.start_attribute("=".to_string());
+					self.state = State::AttributeName;
+				}
+				Some(_) => {
+					self.start_attribute(String::new());
+					self.reconsume_in(State::AttributeName);
+				}
+			},
+			State::AttributeName => match next {
+				Some(c) if is_html_whitespace(c) => {
+					self.finish_attribute_name();
+					self.reconsume_in(State::AfterAttributeName);
+				}
+				Some('/' | '>') | None => {
+					self.finish_attribute_name();
+					self.reconsume_in(State::AfterAttributeName);
+				}
+				Some('=') => {
+					self.finish_attribute_name();
+					self.state = State::BeforeAttributeValue;
+				}
+				Some('\0') => {
+					self.error("unexpected-null-character");
+					self.push_to_attribute_name('\u{FFFD}');
+				}
+				Some(c @ ('"' | '\'' | '<')) => {
+					self.error("unexpected-character-in-attribute-name");
+					self.push_to_attribute_name(c);
+				}
+				Some(c) => self.push_to_attribute_name(c.to_ascii_lowercase()),
+			},
+			State::AfterAttributeName => match next {
+				Some(c) if is_html_whitespace(c) => {}
+				Some('/') => self.state = State::SelfClosingStartTag,
+				Some('=') => self.state = State::BeforeAttributeValue,
+				Some('>') => {
+					self.state = State::Data;
+					self.emit_current_tag();
+				}
+				Some(_) => {
+					self.start_attribute(String::new());
+					self.reconsume_in(State::AttributeName);
+				}
+				None => {
+					self.error("eof-in-tag");
+					self.emit_eof();
+				}
+			},
+			State::BeforeAttributeValue => match next {
+				Some(c) if is_html_whitespace(c) => {}
+				Some('"') => self.state = State::AttributeValueDoubleQuoted,
+				Some('\'') => self.state = State::AttributeValueSingleQuoted,
+				Some('>') => {
+					self.error("missing-attribute-value");
+					self.state = State::Data;
+					self.emit_current_tag();
+				}
+				_ => self.reconsume_in(State::AttributeValueUnquoted),
+			},
+			State::AttributeValueDoubleQuoted => self.quoted_attribute_value(next, '"'),
+			State::AttributeValueSingleQuoted => self.quoted_attribute_value(next, '\''),
+			State::AttributeValueUnquoted => match next {
+				Some(c) if is_html_whitespace(c) => self.state = State::BeforeAttributeName,
+				Some('&') => self.begin_character_reference(),
+				Some('>') => {
+					self.state = State::Data;
+					self.emit_current_tag();
+				}
+				Some('\0') => {
+					self.error("unexpected-null-character");
+					self.push_to_attribute_value('\u{FFFD}');
+				}
+				Some(c @ ('"' | '\'' | '<' | '=' | '`')) => {
+					self.error("unexpected-character-in-unquoted-attribute-value");
+					self.push_to_attribute_value(c);
+				}
+				Some(c) => self.push_to_attribute_value(c),
+				None => {
+					self.error("eof-in-tag");
+					self.emit_eof();
+				}
+			},
+			State::AfterAttributeValueQuoted => match next {
+				Some(c) if is_html_whitespace(c) => self.state = State::BeforeAttributeName,
+				Some('/') => self.state = State::SelfClosingStartTag,
+				Some('>') => {
+					self.state = State::Data;
+					self.emit_current_tag();
+				}
+				Some(_) => {
+					self.error("missing-whitespace-between-attributes");
+					self.reconsume_in(State::BeforeAttributeName);
+				}
+				None => {
+					self.error("eof-in-tag");
+					self.emit_eof();
+				}
+			},
+			State::SelfClosingStartTag => match next {
+				Some('>') => {
+					self.current_tag.self_closing = true;
+					self.state = State::Data;
+					self.emit_current_tag();
+				}
+				Some(_) => {
+					self.error("unexpected-solidus-in-tag");
+					self.reconsume_in(State::BeforeAttributeName);
+				}
+				None => {
+					self.error("eof-in-tag");
+					self.emit_eof();
+				}
+			},
+			State::BogusComment => match next {
+				Some('>') => {
+					self.state = State::Data;
+					self.emit_current_comment();
+				}
+				Some('\0') => {
+					self.error("unexpected-null-character");
+					self.current_comment.push('\u{FFFD}');
+				}
+				Some(c) => self.current_comment.push(c),
+				None => {
+					self.emit_current_comment();
+					self.emit_eof();
+				}
+			},
+			State::MarkupDeclarationOpen | State::NamedCharacterReference | State::NumericCharacterReferenceEnd => {
+				unreachable!("handled before consuming")
+			}
+			State::CommentStart => match next {
+				Some('-') => self.state = State::CommentStartDash,
+				Some('>') => {
+					self.error("abrupt-closing-of-empty-comment");
+					self.state = State::Data;
+					self.emit_current_comment();
+				}
+				_ => self.reconsume_in(State::Comment),
+			},
+			State::CommentStartDash => match next {
+				Some('-') => self.state = State::CommentEnd,
+				Some('>') => {
+					self.error("abrupt-closing-of-empty-comment");
+					self.state = State::Data;
+					self.emit_current_comment();
+				}
+				Some(_) => {
+					self.current_comment.push('-');
+					self.reconsume_in(State::Comment);
+				}
+				None => self.eof_in_comment(),
+			},
+			State::Comment => match next {
+				Some('<') => {
+					self.current_comment.push('<');
+					self.state = State::CommentLessThanSign;
+				}
+				Some('-') => self.state = State::CommentEndDash,
+				Some('\0') => {
+					self.error("unexpected-null-character");
+					self.current_comment.push('\u{FFFD}');
+				}
+				Some(c) => self.current_comment.push(c),
+				None => self.eof_in_comment(),
+			},
+			State::CommentLessThanSign => match next {
+				Some('!') => {
+					self.current_comment.push('!');
+					self.state = State::CommentLessThanSignBang;
+				}
+				Some('<') => self.current_comment.push('<'),
+				_ => self.reconsume_in(State::Comment),
+			},
+			State::CommentLessThanSignBang => match next {
+				Some('-') => self.state = State::CommentLessThanSignBangDash,
+				_ => self.reconsume_in(State::Comment),
+			},
+			State::CommentLessThanSignBangDash => match next {
+				Some('-') => self.state = State::CommentLessThanSignBangDashDash,
+				_ => self.reconsume_in(State::CommentEndDash),
+			},
+			State::CommentLessThanSignBangDashDash => match next {
+				Some('>') | None => self.reconsume_in(State::CommentEnd),
+				Some(_) => {
+					self.error("nested-comment");
+					self.reconsume_in(State::CommentEnd);
+				}
+			},
+			State::CommentEndDash => match next {
+				Some('-') => self.state = State::CommentEnd,
+				Some(_) => {
+					self.current_comment.push('-');
+					self.reconsume_in(State::Comment);
+				}
+				None => self.eof_in_comment(),
+			},
+			State::CommentEnd => match next {
+				Some('>') => {
+					self.state = State::Data;
+					self.emit_current_comment();
+				}
+				Some('!') => self.state = State::CommentEndBang,
+				Some('-') => self.current_comment.push('-'),
+				Some(_) => {
+					self.current_comment.push_str("--");
+					self.reconsume_in(State::Comment);
+				}
+				None => self.eof_in_comment(),
+			},
+			State::CommentEndBang => match next {
+				Some('-') => {
+					self.current_comment.push_str("--!");
+					self.state = State::CommentEndDash;
+				}
+				Some('>') => {
+					self.error("incorrectly-closed-comment");
+					self.state = State::Data;
+					self.emit_current_comment();
+				}
+				Some(_) => {
+					self.current_comment.push_str("--!");
+					self.reconsume_in(State::Comment);
+				}
+				None => self.eof_in_comment(),
+			},
+			State::Doctype => match next {
+				Some(c) if is_html_whitespace(c) => self.state = State::BeforeDoctypeName,
+				Some('>') => self.reconsume_in(State::BeforeDoctypeName),
+				Some(_) => {
+					self.error("missing-whitespace-before-doctype-name");
+					self.reconsume_in(State::BeforeDoctypeName);
+				}
+				None => self.eof_in_doctype(),
+			},
+			State::BeforeDoctypeName => match next {
+				Some(c) if is_html_whitespace(c) => {}
+				Some('>') => {
+					self.error("missing-doctype-name");
+					self.state = State::Data;
+					self.emit_doctype_with_force_quirks();
+				}
+				Some(c) => {
+					let c = if c == '\0' {
+						self.error("unexpected-null-character");
+						'\u{FFFD}'
+					} else {
+						c.to_ascii_lowercase()
+					};
+					self.current_doctype.name = Some(c.to_string());
+					self.state = State::DoctypeName;
+				}
+				None => self.eof_in_doctype(),
+			},
+			State::DoctypeName => match next {
+				Some(c) if is_html_whitespace(c) => self.state = State::AfterDoctypeName,
+				Some('>') => {
+					self.state = State::Data;
+					self.emit_current_doctype();
+				}
+				Some(c) => {
+					let c = if c == '\0' {
+						self.error("unexpected-null-character");
+						'\u{FFFD}'
+					} else {
+						c.to_ascii_lowercase()
+					};
+					self.current_doctype.name.get_or_insert_with(String::new).push(c);
+				}
+				None => self.eof_in_doctype(),
+			},
+			State::AfterDoctypeName => match next {
+				Some(c) if is_html_whitespace(c) => {}
+				Some('>') => {
+					self.state = State::Data;
+					self.emit_current_doctype();
+				}
+				Some('p' | 'P') if self.consume_keyword_rest("PUBLIC") => {
+					self.state = State::AfterDoctypePublicKeyword;
+				}
+				Some('s' | 'S') if self.consume_keyword_rest("SYSTEM") => {
+					self.state = State::AfterDoctypeSystemKeyword;
+				}
+				Some(_) => {
+					self.error("invalid-character-sequence-after-doctype-name");
+					self.current_doctype.force_quirks = true;
+					self.reconsume_in(State::BogusDoctype);
+				}
+				None => self.eof_in_doctype(),
+			},
+			State::AfterDoctypePublicKeyword => match next {
+				Some(c) if is_html_whitespace(c) => self.state = State::BeforeDoctypePublicIdentifier,
+				Some(quote @ ('"' | '\'')) => {
+					self.error("missing-whitespace-after-doctype-public-keyword");
+					self.begin_public_identifier(quote);
+				}
+				_ => self.before_doctype_public_identifier(next),
+			},
+			State::BeforeDoctypePublicIdentifier => match next {
+				Some(c) if is_html_whitespace(c) => {}
+				Some(quote @ ('"' | '\'')) => self.begin_public_identifier(quote),
+				_ => self.before_doctype_public_identifier(next),
+			},
+			State::DoctypePublicIdentifierDoubleQuoted => self.doctype_identifier(next, '"', true),
+			State::DoctypePublicIdentifierSingleQuoted => self.doctype_identifier(next, '\'', true),
+			State::AfterDoctypePublicIdentifier => match next {
+				Some(c) if is_html_whitespace(c) => self.state = State::BetweenDoctypePublicAndSystemIdentifiers,
+				Some('>') => {
+					self.state = State::Data;
+					self.emit_current_doctype();
+				}
+				Some(quote @ ('"' | '\'')) => {
+					self.error("missing-whitespace-between-doctype-public-and-system-identifiers");
+					self.begin_system_identifier(quote);
+				}
+				_ => self.missing_quote_before_system_identifier(next),
+			},
+			State::BetweenDoctypePublicAndSystemIdentifiers => match next {
+				Some(c) if is_html_whitespace(c) => {}
+				Some('>') => {
+					self.state = State::Data;
+					self.emit_current_doctype();
+				}
+				Some(quote @ ('"' | '\'')) => self.begin_system_identifier(quote),
+				_ => self.missing_quote_before_system_identifier(next),
+			},
+			State::AfterDoctypeSystemKeyword => match next {
+				Some(c) if is_html_whitespace(c) => self.state = State::BeforeDoctypeSystemIdentifier,
+				Some(quote @ ('"' | '\'')) => {
+					self.error("missing-whitespace-after-doctype-system-keyword");
+					self.begin_system_identifier(quote);
+				}
+				_ => self.before_doctype_system_identifier(next),
+			},
+			State::BeforeDoctypeSystemIdentifier => match next {
+				Some(c) if is_html_whitespace(c) => {}
+				Some(quote @ ('"' | '\'')) => self.begin_system_identifier(quote),
+				_ => self.before_doctype_system_identifier(next),
+			},
+			State::DoctypeSystemIdentifierDoubleQuoted => self.doctype_identifier(next, '"', false),
+			State::DoctypeSystemIdentifierSingleQuoted => self.doctype_identifier(next, '\'', false),
+			State::AfterDoctypeSystemIdentifier => match next {
+				Some(c) if is_html_whitespace(c) => {}
+				Some('>') => {
+					self.state = State::Data;
+					self.emit_current_doctype();
+				}
+				Some(_) => {
+					self.error("unexpected-character-after-doctype-system-identifier");
+					self.reconsume_in(State::BogusDoctype);
+				}
+				None => self.eof_in_doctype(),
+			},
+			State::BogusDoctype => match next {
+				Some('>') => {
+					self.state = State::Data;
+					self.emit_current_doctype();
+				}
+				Some('\0') => self.error("unexpected-null-character"),
+				Some(_) => {}
+				None => {
+					self.emit_current_doctype();
+					self.emit_eof();
+				}
+			},
+			State::CdataSection => match next {
+				Some(']') => self.state = State::CdataSectionBracket,
+				Some(c) => self.emit_char(c),
+				None => {
+					self.error("eof-in-cdata");
+					self.emit_eof();
+				}
+			},
+			State::CdataSectionBracket => match next {
+				Some(']') => self.state = State::CdataSectionEnd,
+				_ => {
+					self.emit_char(']');
+					self.reconsume_in(State::CdataSection);
+				}
+			},
+			State::CdataSectionEnd => match next {
+				Some(']') => self.emit_char(']'),
+				Some('>') => self.state = State::Data,
+				_ => {
+					self.emit_str("]]");
+					self.reconsume_in(State::CdataSection);
+				}
+			},
+			State::CharacterReference => {
+				self.temporary_buffer.clear();
+				self.temporary_buffer.push('&');
+				match next {
+					Some(c) if c.is_ascii_alphanumeric() => self.reconsume_in(State::NamedCharacterReference),
+					Some('#') => {
+						self.temporary_buffer.push('#');
+						self.state = State::NumericCharacterReference;
+					}
+					_ => {
+						self.flush_character_reference();
+						self.reconsume_in(self.return_state);
+					}
+				}
+			}
+			State::AmbiguousAmpersand => match next {
+				Some(c) if c.is_ascii_alphanumeric() => {
+					if self.in_attribute_value() {
+						self.push_to_attribute_value(c);
+					} else {
+						self.emit_char(c);
+					}
+				}
+				Some(';') => {
+					self.error("unknown-named-character-reference");
+					self.reconsume_in(self.return_state);
+				}
+				_ => self.reconsume_in(self.return_state),
+			},
+			State::NumericCharacterReference => {
+				self.character_reference_code = 0;
+				match next {
+					Some(c @ ('x' | 'X')) => {
+						self.temporary_buffer.push(c);
+						self.state = State::HexadecimalCharacterReferenceStart;
+					}
+					_ => self.reconsume_in(State::DecimalCharacterReferenceStart),
+				}
+			}
+			State::HexadecimalCharacterReferenceStart => match next {
+				Some(c) if c.is_ascii_hexdigit() => self.reconsume_in(State::HexadecimalCharacterReference),
+				_ => self.absence_of_digits(),
+			},
+			State::DecimalCharacterReferenceStart => match next {
+				Some(c) if c.is_ascii_digit() => self.reconsume_in(State::DecimalCharacterReference),
+				_ => self.absence_of_digits(),
+			},
+			State::HexadecimalCharacterReference => self.numeric_character_reference_digit(next, 16),
+			State::DecimalCharacterReference => self.numeric_character_reference_digit(next, 10),
+		}
+	}
+
+	fn begin_character_reference(&mut self) {
+		self.return_state = self.state;
+		self.character_reference_start = self.current_start;
+		self.state = State::CharacterReference;
+	}
+
+	fn in_attribute_value(&self) -> bool {
+		matches!(
+			self.return_state,
+			State::AttributeValueDoubleQuoted | State::AttributeValueSingleQuoted | State::AttributeValueUnquoted
+		)
+	}
+
+	/// Appends the temporary buffer to the attribute value, or emits it as
+	/// characters, depending on where the character reference appeared.
+	fn flush_character_reference(&mut self) {
+		let buffer = std::mem::take(&mut self.temporary_buffer);
+		if self.in_attribute_value() {
+			if let Some((_, value)) = self.current_tag.attributes.last_mut() {
+				value.push_str(&buffer);
+			}
+		} else {
+			for c in buffer.chars() {
+				self.emit(Token::Character(c), self.character_reference_start);
+			}
+		}
+	}
+
+	fn named_character_reference(&mut self) {
+		// No name is longer than this, so there's no need to look further.
+		let mut lookahead = String::new();
+		if self.reconsume {
+			lookahead.extend(self.current_char);
+		}
+		lookahead.extend(self.parser.remaining().chars().take(entities::LONGEST_NAME));
+
+		let Some((name, replacement)) = entities::longest_match(&lookahead) else {
+			self.flush_character_reference();
+			self.state = State::AmbiguousAmpersand;
+			return;
+		};
+
+		let mut unconsumed = name.chars().count();
+		if self.reconsume {
+			self.reconsume = false;
+			unconsumed -= 1;
+		}
+		for _ in 0..unconsumed {
+			self.consume();
+		}
+		self.temporary_buffer.push_str(name);
+
+		let terminated = name.ends_with(';');
+		let followed_by_name_char = self.parser.next_char().is_some_and(|c| c == '=' || c.is_ascii_alphanumeric());
+		// For historical reasons, `&copy=` and the like are left alone in
+		// attribute values.
+		if !terminated && self.in_attribute_value() && followed_by_name_char {
+			self.flush_character_reference();
+			self.state = self.return_state;
+			return;
+		}
+		if !terminated {
+			self.error("missing-semicolon-after-character-reference");
+		}
+		self.temporary_buffer = replacement.to_string();
+		self.flush_character_reference();
+		self.state = self.return_state;
+	}
+
+	fn absence_of_digits(&mut self) {
+		self.error("absence-of-digits-in-numeric-character-reference");
+		self.flush_character_reference();
+		self.reconsume_in(self.return_state);
+	}
+
+	fn numeric_character_reference_digit(&mut self, next: Option<char>, radix: u32) {
+		match next.and_then(|c| c.to_digit(radix)) {
+			Some(digit) => {
+				// Anything past the Unicode range is an error either way, so
+				// there's no need to keep counting.
+				self.character_reference_code = (self.character_reference_code * radix + digit).min(0x110000);
+			}
+			None if next == Some(';') => self.state = State::NumericCharacterReferenceEnd,
+			None => {
+				self.error("missing-semicolon-after-character-reference");
+				self.reconsume_in(State::NumericCharacterReferenceEnd);
+			}
+		}
+	}
+
+	fn numeric_character_reference_end(&mut self) {
+		let code = self.character_reference_code;
+		let replacement = match code {
+			0 => {
+				self.error("null-character-reference");
+				'\u{FFFD}'
+			}
+			0x110000.. => {
+				self.error("character-reference-outside-unicode-range");
+				'\u{FFFD}'
+			}
+			0xD800..=0xDFFF => {
+				self.error("surrogate-character-reference");
+				'\u{FFFD}'
+			}
+			_ => {
+				let noncharacter = (0xFDD0..=0xFDEF).contains(&code) || code & 0xFFFE == 0xFFFE;
+				if noncharacter {
+					self.error("noncharacter-character-reference");
+				}
+				let control = code < 0x20 && !matches!(code, 0x09 | 0x0A | 0x0C) || (0x7F..=0x9F).contains(&code);
+				if control {
+					self.error("control-character-reference");
+				}
+				// References to C1 controls are taken to mean windows-1252.
+				match u8::try_from(code) {
+					Ok(byte @ 0x80..=0x9F) => encoding::windows_1252(byte),
+					_ => char::from_u32(code).unwrap_or('\u{FFFD}'),
+				}
+			}
+		};
+		self.temporary_buffer.clear();
+		self.temporary_buffer.push(replacement);
+		self.flush_character_reference();
+		self.state = self.return_state;
+	}
+
+	fn markup_declaration_open(&mut self) {
+		self.current_start = self.parser.location();
+		if self.parser.starts_with("--") {
+			self.parser.consume_char();
+			self.parser.consume_char();
+			self.current_comment.clear();
+			self.state = State::CommentStart;
+		} else if self.parser.starts_with_ignore_ascii_case("DOCTYPE") {
+			for _ in 0.."DOCTYPE".len() {
+				self.parser.consume_char();
+			}
+			self.current_doctype = DoctypeToken::default();
+			self.state = State::Doctype;
+		} else if self.parser.starts_with("[CDATA[") {
+			for _ in 0.."[CDATA[".len() {
+				self.parser.consume_char();
+			}
+			if self.in_foreign_content {
+				self.state = State::CdataSection;
+			} else {
+				self.error("cdata-in-html-content");
+				self.current_comment = "[CDATA[".to_string();
+				self.state = State::BogusComment;
+			}
+		} else {
+			self.error("incorrectly-opened-comment");
+			self.current_comment.clear();
+			self.state = State::BogusComment;
+		}
+	}
+
+	/// Emits a character from one of the text states, replacing NULL.
+	fn emit_text_char(&mut self, c: char) {
+		if c == '\0' {
+			self.error("unexpected-null-character");
+			self.emit_char('\u{FFFD}');
+		} else {
+			self.emit_char(c);
+		}
+	}
+
+	/// Remembers where a `<` in one of the text states was, in case it turns
+	/// out to start an end tag.
+	fn enter_less_than_sign(&mut self, state: State) {
+		self.token_start = self.current_start;
+		self.state = state;
+	}
+
+	fn less_than_sign_in_text(&mut self, next: Option<char>, text_state: State, end_tag_open_state: State) {
+		match next {
+			Some('/') => {
+				self.temporary_buffer.clear();
+				self.state = end_tag_open_state;
+			}
+			_ => {
+				self.emit(Token::Character('<'), self.token_start);
+				self.reconsume_in(text_state);
+			}
+		}
+	}
+
+	fn end_tag_open_in_text(&mut self, next: Option<char>, text_state: State, end_tag_name_state: State) {
+		match next {
+			Some(c) if c.is_ascii_alphabetic() => {
+				self.begin_tag(true);
+				self.reconsume_in(end_tag_name_state);
+			}
+			_ => {
+				self.emit_str("</");
+				self.reconsume_in(text_state);
+			}
+		}
+	}
+
+	fn end_tag_name_in_text(&mut self, next: Option<char>, text_state: State) {
+		match next {
+			Some(c) if is_html_whitespace(c) && self.is_appropriate_end_tag() => {
+				self.state = State::BeforeAttributeName;
+			}
+			Some('/') if self.is_appropriate_end_tag() => self.state = State::SelfClosingStartTag,
+			Some('>') if self.is_appropriate_end_tag() => {
+				self.state = State::Data;
+				self.emit_current_tag();
+			}
+			Some(c) if c.is_ascii_alphabetic() => {
+				self.current_tag.name.push(c.to_ascii_lowercase());
+				self.temporary_buffer.push(c);
+			}
+			_ => {
+				self.emit_str("</");
+				self.emit_temporary_buffer();
+				self.reconsume_in(text_state);
+			}
+		}
+	}
+
+	/// The script data double escape start and end states, which differ
+	/// only in which way they switch when `script` is seen.
+	fn double_escape_boundary(&mut self, next: Option<char>, if_script: State, otherwise: State) {
+		match next {
+			Some(c) if is_html_whitespace(c) || c == '/' || c == '>' => {
+				self.state = if self.temporary_buffer == "script" { if_script } else { otherwise };
+				self.emit_char(c);
+			}
+			Some(c) if c.is_ascii_alphabetic() => {
+				self.temporary_buffer.push(c.to_ascii_lowercase());
+				self.emit_char(c);
+			}
+			_ => self.reconsume_in(otherwise),
+		}
+	}
+
+	fn quoted_attribute_value(&mut self, next: Option<char>, quote: char) {
+		match next {
+			Some(c) if c == quote => self.state = State::AfterAttributeValueQuoted,
+			Some('&') => self.begin_character_reference(),
+			Some('\0') => {
+				self.error("unexpected-null-character");
+				self.push_to_attribute_value('\u{FFFD}');
+			}
+			Some(c) => self.push_to_attribute_value(c),
+			None => {
+				self.error("eof-in-tag");
+				self.emit_eof();
+			}
+		}
+	}
+
+	fn begin_public_identifier(&mut self, quote: char) {
+		self.current_doctype.public_id = Some(String::new());
+		self.state = if quote == '"' {
+			State::DoctypePublicIdentifierDoubleQuoted
+		} else {
+			State::DoctypePublicIdentifierSingleQuoted
+		};
+	}
+
+	fn begin_system_identifier(&mut self, quote: char) {
+		self.current_doctype.system_id = Some(String::new());
+		self.state = if quote == '"' {
+			State::DoctypeSystemIdentifierDoubleQuoted
+		} else {
+			State::DoctypeSystemIdentifierSingleQuoted
+		};
+	}
+
+	fn before_doctype_public_identifier(&mut self, next: Option<char>) {
+		match next {
+			Some('>') => {
+				self.error("missing-doctype-public-identifier");
+				self.state = State::Data;
+				self.emit_doctype_with_force_quirks();
+			}
+			Some(_) => {
+				self.error("missing-quote-before-doctype-public-identifier");
+				self.current_doctype.force_quirks = true;
+				self.reconsume_in(State::BogusDoctype);
+			}
+			None => self.eof_in_doctype(),
+		}
+	}
+
+	fn before_doctype_system_identifier(&mut self, next: Option<char>) {
+		match next {
+			Some('>') => {
+				self.error("missing-doctype-system-identifier");
+				self.state = State::Data;
+				self.emit_doctype_with_force_quirks();
+			}
+			_ => self.missing_quote_before_system_identifier(next),
+		}
+	}
+
+	fn missing_quote_before_system_identifier(&mut self, next: Option<char>) {
+		match next {
+			Some(_) => {
+				self.error("missing-quote-before-doctype-system-identifier");
+				self.current_doctype.force_quirks = true;
+				self.reconsume_in(State::BogusDoctype);
+			}
+			None => self.eof_in_doctype(),
+		}
+	}
+
+	fn doctype_identifier(&mut self, next: Option<char>, quote: char, public: bool) {
+		let (after_state, abrupt_error) = if public {
+			(State::AfterDoctypePublicIdentifier, "abrupt-doctype-public-identifier")
+		} else {
+			(State::AfterDoctypeSystemIdentifier, "abrupt-doctype-system-identifier")
+		};
+		match next {
+			Some(c) if c == quote => self.state = after_state,
+			Some('>') => {
+				self.error(abrupt_error);
+				self.state = State::Data;
+				self.emit_doctype_with_force_quirks();
+			}
+			Some(c) => {
+				let c = if c == '\0' {
+					self.error("unexpected-null-character");
+					'\u{FFFD}'
+				} else {
+					c
+				};
+				let identifier = if public {
+					&mut self.current_doctype.public_id
+				} else {
+					&mut self.current_doctype.system_id
+				};
+				identifier.get_or_insert_with(String::new).push(c);
+			}
+			None => self.eof_in_doctype(),
+		}
+	}
+
+	fn eof_in_comment(&mut self) {
+		self.error("eof-in-comment");
+		self.emit_current_comment();
+		self.emit_eof();
+	}
+
+	fn eof_in_doctype(&mut self) {
+		self.error("eof-in-doctype");
+		self.emit_doctype_with_force_quirks();
+		self.emit_eof();
+	}
+
+	fn eof_in_script_comment(&mut self) {
+		self.error("eof-in-script-html-comment-like-text");
+		self.emit_eof();
+	}
+}
+
+impl Iterator for Tokenizer {
+	type Item = (Token, Span);
+
+	fn next(&mut self) -> Option<(Token, Span)> {
+		self.next_token()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Tokenizes `source`, merging runs of characters into one string.
+	fn tokens(source: &str) -> Vec<String> {
+		let mut tokens: Vec<String> = Vec::new();
+		let mut text = String::new();
+		for (token, _) in Tokenizer::new(source.to_string()) {
+			if let Token::Character(c) = token {
+				text.push(c);
+				continue;
+			}
+			if !text.is_empty() {
+				tokens.push(format!("{:?}", std::mem::take(&mut text)));
+			}
+			match token {
+				Token::StartTag(tag) => {
+					let attributes: String =
+						tag.attributes.iter().map(|(name, value)| format!(" {}={:?}", name, value)).collect();
+					let slash = if tag.self_closing { "/" } else { "" };
+					tokens.push(format!("<{}{}{}>", tag.name, attributes, slash));
+				}
+				Token::EndTag(tag) => tokens.push(format!("</{}>", tag.name)),
+				Token::Comment(comment) => tokens.push(format!("<!--{}-->", comment)),
+				Token::Doctype(doctype) => tokens.push(format!("<!DOCTYPE {:?}>", doctype.name)),
+				Token::Character(_) | Token::EndOfFile => {}
+			}
+		}
+		if !text.is_empty() {
+			tokens.push(format!("{:?}", text));
+		}
+		tokens
+	}
+
+	#[test]
+	fn tags_and_attributes() {
+		assert_eq!(
+			tokens("<DIV Class=a id='b' data-x=\"c d\" hidden><br/></div>"),
+			[r#"<div class="a" id="b" data-x="c d" hidden="">"#, "<br/>", "</div>"]
+		);
+	}
+
+	#[test]
+	fn duplicate_attributes_keep_the_first() {
+		assert_eq!(tokens("<p a=1 A=2 b=3>"), [r#"<p a="1" b="3">"#]);
+	}
+
+	#[test]
+	fn character_references() {
+		assert_eq!(
+			tokens("&amp; &lt;b&gt; &#169; &#x41; &notit; &bogus; <a href='?a=1&copy=2'>"),
+			[r#""& <b> © A ¬it; &bogus; ""#, r#"<a href="?a=1&copy=2">"#]
+		);
+	}
+
+	#[test]
+	fn longest_character_reference_at_end_of_input() {
+		assert_eq!(tokens("&CounterClockwiseContourIntegral;"), ["\"\u{2233}\""]);
+		assert_eq!(tokens("&CounterClockwiseContourIntegral"), ["\"&CounterClockwiseContourIntegral\""]);
+	}
+
+	#[test]
+	fn comments_and_doctype() {
+		assert_eq!(
+			tokens("<!doctype html><!-- a -- b --><!--->x<?php?>"),
+			[r#"<!DOCTYPE Some("html")>"#, "<!-- a -- b -->", "<!---->", "\"x\"", "<!--?php?-->"]
+		);
+	}
+
+	#[test]
+	fn raw_text_ends_only_at_its_end_tag() {
+		let mut tokenizer = Tokenizer::new("a<b>&amp;</style></script>".to_string());
+		tokenizer.set_state(State::ScriptData);
+		tokenizer.last_start_tag_name = Some("script".to_string());
+		let text: String = tokenizer
+			.map_while(|(token, _)| match token {
+				Token::Character(c) => Some(c),
+				_ => None,
+			})
+			.collect();
+		assert_eq!(text, "a<b>&amp;</style>");
+	}
+}
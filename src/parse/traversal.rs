@@ -0,0 +1,505 @@
+use crate::parse::dom::{Document, NodeData, NodeId};
+
+/// Walking a document. These follow the links between nodes rather than
+/// recursing, so they work on documents of any depth.
+impl Document {
+	/// The children of `id`, first to last.
+	pub fn children(&self, id: NodeId) -> Links<'_> {
+		Links { document: self, next: self[id].first_child(), link: NodeData::next_sibling }
+	}
+
+	/// The parent of `id`, then its parent, and so on up to the root.
+	pub fn ancestors(&self, id: NodeId) -> Links<'_> {
+		Links { document: self, next: self[id].parent(), link: NodeData::parent }
+	}
+
+	/// The siblings after `id`, nearest first.
+	pub fn following_siblings(&self, id: NodeId) -> Links<'_> {
+		Links { document: self, next: self[id].next_sibling(), link: NodeData::next_sibling }
+	}
+
+	/// The siblings before `id`, nearest first.
+	pub fn preceding_siblings(&self, id: NodeId) -> Links<'_> {
+		Links { document: self, next: self[id].previous_sibling(), link: NodeData::previous_sibling }
+	}
+
+	/// Every node under `id` in document order, each before its children,
+	/// not including `id` itself.
+	pub fn descendants(&self, id: NodeId) -> Descendants<'_> {
+		Descendants { document: self, root: id, next: self[id].first_child() }
+	}
+
+	/// Every node under `id`, each after its children, not including `id`
+	/// itself.
+	pub fn descendants_post_order(&self, id: NodeId) -> PostOrder<'_> {
+		let next = self[id].first_child().map(|child| self.first_leaf(child));
+		PostOrder { document: self, root: id, next }
+	}
+
+	/// Every node after `id` in document order, starting with its
+	/// descendants.
+	pub fn following(&self, id: NodeId) -> Following<'_> {
+		Following { document: self, next: self.next_in_tree_order(id, None) }
+	}
+
+	/// Every node before `id` in document order, nearest first. As in the
+	/// DOM, that includes its ancestors.
+	pub fn preceding(&self, id: NodeId) -> Preceding<'_> {
+		Preceding { document: self, next: self.previous_in_tree_order(id) }
+	}
+
+	/// The node after `id` in document order, without leaving `root`.
+	fn next_in_tree_order(&self, id: NodeId, root: Option<NodeId>) -> Option<NodeId> {
+		self[id].first_child().or_else(|| self.next_skipping_children(id, root))
+	}
+
+	/// The next sibling of `id` or of its nearest ancestor that has one,
+	/// without leaving `root`.
+	fn next_skipping_children(&self, mut id: NodeId, root: Option<NodeId>) -> Option<NodeId> {
+		while Some(id) != root {
+			let node = &self[id];
+			if node.next_sibling().is_some() {
+				return node.next_sibling();
+			}
+			id = node.parent()?;
+		}
+		None
+	}
+
+	fn previous_in_tree_order(&self, id: NodeId) -> Option<NodeId> {
+		match self[id].previous_sibling() {
+			Some(sibling) => Some(self.last_leaf(sibling)),
+			None => self[id].parent(),
+		}
+	}
+
+	/// `id`'s first child's first child, and so on, as far as it goes.
+	fn first_leaf(&self, mut id: NodeId) -> NodeId {
+		while let Some(child) = self[id].first_child() {
+			id = child;
+		}
+		id
+	}
+
+	fn last_leaf(&self, mut id: NodeId) -> NodeId {
+		while let Some(child) = self[id].last_child() {
+			id = child;
+		}
+		id
+	}
+}
+
+type Link = fn(&NodeData) -> Option<NodeId>;
+
+/// Follows one kind of link from node to node, such as each node's next
+/// sibling or its parent.
+pub struct Links<'a> {
+	document: &'a Document,
+	next: Option<NodeId>,
+	link: Link,
+}
+
+impl Iterator for Links<'_> {
+	type Item = NodeId;
+
+	fn next(&mut self) -> Option<NodeId> {
+		let id = self.next?;
+		self.next = (self.link)(&self.document[id]);
+		Some(id)
+	}
+}
+
+pub struct Descendants<'a> {
+	document: &'a Document,
+	root: NodeId,
+	next: Option<NodeId>,
+}
+
+impl Iterator for Descendants<'_> {
+	type Item = NodeId;
+
+	fn next(&mut self) -> Option<NodeId> {
+		let id = self.next?;
+		self.next = self.document.next_in_tree_order(id, Some(self.root));
+		Some(id)
+	}
+}
+
+pub struct PostOrder<'a> {
+	document: &'a Document,
+	root: NodeId,
+	next: Option<NodeId>,
+}
+
+impl Iterator for PostOrder<'_> {
+	type Item = NodeId;
+
+	fn next(&mut self) -> Option<NodeId> {
+		let id = self.next?;
+		let node = &self.document[id];
+		self.next = match node.next_sibling() {
+			Some(sibling) => Some(self.document.first_leaf(sibling)),
+			None => node.parent().filter(|&parent| parent != self.root),
+		};
+		Some(id)
+	}
+}
+
+pub struct Following<'a> {
+	document: &'a Document,
+	next: Option<NodeId>,
+}
+
+impl Iterator for Following<'_> {
+	type Item = NodeId;
+
+	fn next(&mut self) -> Option<NodeId> {
+		let id = self.next?;
+		self.next = self.document.next_in_tree_order(id, None);
+		Some(id)
+	}
+}
+
+pub struct Preceding<'a> {
+	document: &'a Document,
+	next: Option<NodeId>,
+}
+
+impl Iterator for Preceding<'_> {
+	type Item = NodeId;
+
+	fn next(&mut self) -> Option<NodeId> {
+		let id = self.next?;
+		self.next = self.document.previous_in_tree_order(id);
+		Some(id)
+	}
+}
+
+/// What a `TreeWalker` does with a node, like the result of a DOM
+/// `NodeFilter`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+	/// Stop at the node.
+	Accept,
+	/// Pass over the node and everything under it.
+	Reject,
+	/// Pass over the node, but not its children.
+	Skip,
+}
+
+/// A cursor that moves around the nodes under `root` that `filter`
+/// accepts, following the DOM's `TreeWalker`. Each move returns the node
+/// moved to, or `None` and stays put if there isn't one.
+///
+/// As an iterator, it moves to each following node in turn.
+pub struct TreeWalker<'a, F> {
+	document: &'a Document,
+	root: NodeId,
+	current: NodeId,
+	filter: F,
+}
+
+impl<'a, F> TreeWalker<'a, F>
+where
+	F: FnMut(&Document, NodeId) -> Filter,
+{
+	pub fn new(document: &'a Document, root: NodeId, filter: F) -> TreeWalker<'a, F> {
+		TreeWalker { document, root, current: root, filter }
+	}
+
+	pub fn root(&self) -> NodeId {
+		self.root
+	}
+
+	pub fn current_node(&self) -> NodeId {
+		self.current
+	}
+
+	pub fn set_current_node(&mut self, id: NodeId) {
+		self.current = id;
+	}
+
+	fn filter(&mut self, id: NodeId) -> Filter {
+		(self.filter)(self.document, id)
+	}
+
+	fn accept(&mut self, id: NodeId) -> Option<NodeId> {
+		self.current = id;
+		Some(id)
+	}
+
+	pub fn parent_node(&mut self) -> Option<NodeId> {
+		let mut id = self.current;
+		while id != self.root {
+			id = self.document[id].parent()?;
+			if self.filter(id) == Filter::Accept {
+				return self.accept(id);
+			}
+		}
+		None
+	}
+
+	pub fn first_child(&mut self) -> Option<NodeId> {
+		self.traverse_children(true)
+	}
+
+	pub fn last_child(&mut self) -> Option<NodeId> {
+		self.traverse_children(false)
+	}
+
+	fn traverse_children(&mut self, first: bool) -> Option<NodeId> {
+		let document = self.document;
+		let (child, sibling): (Link, Link) = match first {
+			true => (NodeData::first_child, NodeData::next_sibling),
+			false => (NodeData::last_child, NodeData::previous_sibling),
+		};
+		let mut next = child(&document[self.current]);
+		while let Some(id) = next {
+			match self.filter(id) {
+				Filter::Accept => return self.accept(id),
+				Filter::Skip if child(&document[id]).is_some() => {
+					next = child(&document[id]);
+					continue;
+				}
+				_ => {}
+			}
+			let mut id = id;
+			loop {
+				if let Some(sibling) = sibling(&document[id]) {
+					next = Some(sibling);
+					break;
+				}
+				match document[id].parent() {
+					Some(parent) if parent != self.root && parent != self.current => id = parent,
+					_ => return None,
+				}
+			}
+		}
+		None
+	}
+
+	pub fn next_sibling(&mut self) -> Option<NodeId> {
+		self.traverse_siblings(true)
+	}
+
+	pub fn previous_sibling(&mut self) -> Option<NodeId> {
+		self.traverse_siblings(false)
+	}
+
+	fn traverse_siblings(&mut self, next: bool) -> Option<NodeId> {
+		let document = self.document;
+		let (sibling_of, child_of): (Link, Link) = match next {
+			true => (NodeData::next_sibling, NodeData::first_child),
+			false => (NodeData::previous_sibling, NodeData::last_child),
+		};
+		let mut id = self.current;
+		if id == self.root {
+			return None;
+		}
+		loop {
+			let mut sibling = sibling_of(&document[id]);
+			while let Some(next) = sibling {
+				id = next;
+				let filter = self.filter(id);
+				if filter == Filter::Accept {
+					return self.accept(id);
+				}
+				sibling = child_of(&document[id]);
+				if filter == Filter::Reject || sibling.is_none() {
+					sibling = sibling_of(&document[id]);
+				}
+			}
+			id = document[id].parent()?;
+			if id == self.root || self.filter(id) == Filter::Accept {
+				return None;
+			}
+		}
+	}
+
+	pub fn previous_node(&mut self) -> Option<NodeId> {
+		let document = self.document;
+		let mut id = self.current;
+		while id != self.root {
+			while let Some(sibling) = document[id].previous_sibling() {
+				id = sibling;
+				let mut filter = self.filter(id);
+				while filter != Filter::Reject {
+					let Some(child) = document[id].last_child() else {
+						break;
+					};
+					id = child;
+					filter = self.filter(id);
+				}
+				if filter == Filter::Accept {
+					return self.accept(id);
+				}
+			}
+			if id == self.root {
+				return None;
+			}
+			id = document[id].parent()?;
+			if self.filter(id) == Filter::Accept {
+				return self.accept(id);
+			}
+		}
+		None
+	}
+
+	pub fn next_node(&mut self) -> Option<NodeId> {
+		let document = self.document;
+		let mut id = self.current;
+		let mut filter = Filter::Accept;
+		loop {
+			while filter != Filter::Reject {
+				let Some(child) = document[id].first_child() else {
+					break;
+				};
+				id = child;
+				filter = self.filter(id);
+				if filter == Filter::Accept {
+					return self.accept(id);
+				}
+			}
+			id = document.next_skipping_children(id, Some(self.root))?;
+			filter = self.filter(id);
+			if filter == Filter::Accept {
+				return self.accept(id);
+			}
+		}
+	}
+}
+
+impl<F> Iterator for TreeWalker<'_, F>
+where
+	F: FnMut(&Document, NodeId) -> Filter,
+{
+	type Item = NodeId;
+
+	fn next(&mut self) -> Option<NodeId> {
+		self.next_node()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::parse::html;
+
+	/// A document with `<div>` as the last element in the body:
+	/// `div(a(b, c), d(e), f)`.
+	fn document() -> Document {
+		Document::from(html::parse("<div><a><b></b><c></c></a><d><e></e></d><f></f></div>".to_string()))
+	}
+
+	fn find(document: &Document, tag_name: &str) -> NodeId {
+		document.get_elements_by_tag_name(document.root(), tag_name)[0]
+	}
+
+	fn names(document: &Document, ids: impl IntoIterator<Item = NodeId>) -> Vec<String> {
+		ids.into_iter()
+			.map(|id| match document.element(id) {
+				Some(element) => element.tag_name.clone(),
+				None => "#document".to_string(),
+			})
+			.collect()
+	}
+
+	#[test]
+	fn links() {
+		let document = document();
+		let (div, c, e) = (find(&document, "div"), find(&document, "c"), find(&document, "e"));
+		assert_eq!(names(&document, document.children(div)), ["a", "d", "f"]);
+		assert_eq!(names(&document, document.ancestors(e)), ["d", "div", "body", "html", "#document"]);
+		assert_eq!(names(&document, document.following_siblings(find(&document, "a"))), ["d", "f"]);
+		assert_eq!(names(&document, document.preceding_siblings(find(&document, "f"))), ["d", "a"]);
+		assert_eq!(names(&document, document.preceding_siblings(c)), ["b"]);
+		assert!(document.children(e).next().is_none());
+	}
+
+	#[test]
+	fn tree_order() {
+		let document = document();
+		let div = find(&document, "div");
+		assert_eq!(names(&document, document.descendants(div)), ["a", "b", "c", "d", "e", "f"]);
+		assert_eq!(names(&document, document.descendants_post_order(div)), ["b", "c", "a", "e", "d", "f"]);
+		assert_eq!(names(&document, document.following(find(&document, "c"))), ["d", "e", "f"]);
+		assert_eq!(
+			names(&document, document.preceding(find(&document, "d"))),
+			["c", "b", "a", "div", "body", "head", "html", "#document"]
+		);
+		assert!(document.descendants(find(&document, "f")).next().is_none());
+	}
+
+	#[test]
+	fn walker_moves_in_document_order() {
+		let document = document();
+		let div = find(&document, "div");
+		let mut walker = TreeWalker::new(&document, div, |_, _| Filter::Accept);
+		assert_eq!(names(&document, walker.by_ref()), ["a", "b", "c", "d", "e", "f"]);
+		assert_eq!(walker.current_node(), find(&document, "f"));
+
+		let mut previous = Vec::new();
+		while let Some(id) = walker.previous_node() {
+			previous.push(id);
+		}
+		assert_eq!(names(&document, previous), ["e", "d", "c", "b", "a", "div"]);
+		assert_eq!(walker.current_node(), div);
+	}
+
+	#[test]
+	fn walker_parent_node_stops_at_the_root() {
+		let document = document();
+		let div = find(&document, "div");
+		let mut walker = TreeWalker::new(&document, div, |_, _| Filter::Accept);
+		walker.set_current_node(find(&document, "e"));
+		assert_eq!(walker.parent_node(), Some(find(&document, "d")));
+		assert_eq!(walker.parent_node(), Some(div));
+		assert_eq!(walker.parent_node(), None);
+		assert_eq!(walker.current_node(), div);
+	}
+
+	#[test]
+	fn walker_children_and_siblings() {
+		let document = document();
+		let div = find(&document, "div");
+		let mut walker = TreeWalker::new(&document, div, |_, _| Filter::Accept);
+		assert_eq!(walker.last_child(), Some(find(&document, "f")));
+		assert_eq!(walker.previous_sibling(), Some(find(&document, "d")));
+		assert_eq!(walker.first_child(), Some(find(&document, "e")));
+		assert_eq!(walker.next_sibling(), None);
+		assert_eq!(walker.current_node(), find(&document, "e"));
+	}
+
+	#[test]
+	fn walker_skips_and_rejects() {
+		let document = document();
+		let div = find(&document, "div");
+		let skip = |tag_name: &'static str| {
+			move |document: &Document, id: NodeId| match document.element(id) {
+				Some(element) if element.tag_name == tag_name => Filter::Skip,
+				_ => Filter::Accept,
+			}
+		};
+		let reject = |tag_name: &'static str| {
+			move |document: &Document, id: NodeId| match document.element(id) {
+				Some(element) if element.tag_name == tag_name => Filter::Reject,
+				_ => Filter::Accept,
+			}
+		};
+		assert_eq!(names(&document, TreeWalker::new(&document, div, skip("a"))), ["b", "c", "d", "e", "f"]);
+		assert_eq!(names(&document, TreeWalker::new(&document, div, reject("a"))), ["d", "e", "f"]);
+
+		// Skipped nodes' children take their place among their siblings.
+		let mut walker = TreeWalker::new(&document, div, skip("d"));
+		walker.set_current_node(find(&document, "a"));
+		assert_eq!(walker.next_sibling(), Some(find(&document, "e")));
+		assert_eq!(walker.next_sibling(), Some(find(&document, "f")));
+		assert_eq!(walker.previous_node(), Some(find(&document, "e")));
+		assert_eq!(walker.parent_node(), Some(div));
+
+		let mut walker = TreeWalker::new(&document, div, reject("a"));
+		assert_eq!(walker.first_child(), Some(find(&document, "d")));
+		assert_eq!(walker.previous_sibling(), None);
+		assert_eq!(walker.current_node(), find(&document, "d"));
+	}
+}
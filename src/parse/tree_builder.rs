@@ -0,0 +1,2142 @@
+use crate::parse::{
+	dom,
+	error::ParseError,
+	span::Span,
+	tokenizer::{self, is_html_whitespace, DoctypeToken, TagToken, Token, Tokenizer},
+};
+
+type NodeId = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Namespace {
+	Html,
+	Svg,
+	MathMl,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InsertionMode {
+	Initial,
+	BeforeHtml,
+	BeforeHead,
+	InHead,
+	InHeadNoscript,
+	AfterHead,
+	InBody,
+	Text,
+	InTable,
+	InTableText,
+	InCaption,
+	InColumnGroup,
+	InTableBody,
+	InRow,
+	InCell,
+	AfterBody,
+	AfterAfterBody,
+}
+
+/// A node in the tree under construction. Nodes live in an arena so the
+/// adoption agency algorithm can move them around by id.
+struct BuilderNode {
+	node_type: dom::NodeType,
+	namespace: Namespace,
+	parent: Option<NodeId>,
+	children: Vec<NodeId>,
+	span: Span,
+}
+
+enum FormattingEntry {
+	Marker,
+	Element(NodeId, TagToken),
+}
+
+/// Builds a DOM from a token stream following the HTML tree construction
+/// algorithm: the stack of open elements, implied end tags, the list of
+/// active formatting elements, the adoption agency algorithm, and the table
+/// insertion modes with foster parenting. The select, template and frameset
+/// insertion modes aren't implemented; those elements are treated like any
+/// other element in body.
+pub struct TreeBuilder {
+	tokenizer: Tokenizer,
+	nodes: Vec<BuilderNode>,
+	document_children: Vec<NodeId>,
+	open_elements: Vec<NodeId>,
+	active_formatting: Vec<FormattingEntry>,
+	mode: InsertionMode,
+	original_mode: InsertionMode,
+	quirks_mode: dom::QuirksMode,
+	head: Option<NodeId>,
+	form: Option<NodeId>,
+	frameset_ok: bool,
+	foster_parenting: bool,
+	pending_table_characters: Vec<(char, Span)>,
+	ignore_next_line_feed: bool,
+	token_span: Span,
+	errors: Vec<ParseError>,
+}
+
+const BLOCK_ELEMENTS: [&str; 25] = [
+	"address", "article", "aside", "blockquote", "center", "details", "dialog", "dir", "div", "dl",
+	"fieldset", "figcaption", "figure", "footer", "header", "hgroup", "main", "menu", "nav", "ol", "p",
+	"search", "section", "summary", "ul",
+];
+
+const HEADINGS: [&str; 6] = ["h1", "h2", "h3", "h4", "h5", "h6"];
+
+const FORMATTING_ELEMENTS: [&str; 14] = [
+	"a", "b", "big", "code", "em", "font", "i", "nobr", "s", "small", "strike", "strong", "tt", "u",
+];
+
+const IMPLIED_END_TAGS: [&str; 10] = ["dd", "dt", "li", "optgroup", "option", "p", "rb", "rp", "rt", "rtc"];
+
+const SPECIAL_ELEMENTS: [&str; 83] = [
+	"address", "applet", "area", "article", "aside", "base", "basefont", "bgsound", "blockquote", "body",
+	"br", "button", "caption", "center", "col", "colgroup", "dd", "details", "dir", "div", "dl", "dt",
+	"embed", "fieldset", "figcaption", "figure", "footer", "form", "frame", "frameset", "h1", "h2", "h3",
+	"h4", "h5", "h6", "head", "header", "hgroup", "hr", "html", "iframe", "img", "input", "keygen", "li",
+	"link", "listing", "main", "marquee", "menu", "meta", "nav", "noembed", "noframes", "noscript",
+	"object", "ol", "p", "param", "plaintext", "pre", "script", "search", "section", "select", "source",
+	"style", "summary", "table", "tbody", "td", "template", "textarea", "tfoot", "th", "thead", "title",
+	"tr", "track", "ul", "wbr", "xmp",
+];
+
+/// Elements whose misplaced content is foster parented before the table.
+const TABLE_CONTEXTS: [&str; 5] = ["table", "tbody", "tfoot", "thead", "tr"];
+
+const SCOPE_BOUNDARIES: [&str; 9] = ["applet", "caption", "html", "table", "td", "th", "marquee", "object", "template"];
+
+const MATHML_TEXT_INTEGRATION_POINTS: [&str; 5] = ["mi", "mo", "mn", "ms", "mtext"];
+
+const SVG_HTML_INTEGRATION_POINTS: [&str; 3] = ["foreignobject", "desc", "title"];
+
+/// Start tags that end foreign content and go back to HTML parsing.
+const FOREIGN_BREAKOUT_ELEMENTS: [&str; 44] = [
+	"b", "big", "blockquote", "body", "br", "center", "code", "dd", "div", "dl", "dt", "em", "embed", "h1",
+	"h2", "h3", "h4", "h5", "h6", "head", "hr", "i", "img", "li", "listing", "menu", "meta", "nobr", "ol",
+	"p", "pre", "ruby", "s", "small", "span", "strong", "strike", "sub", "sup", "table", "tt", "u", "ul",
+	"var",
+];
+
+const QUIRKS_PUBLIC_IDS: [&str; 3] =
+	["-//W3O//DTD W3 HTML Strict 3.0//EN//", "-/W3C/DTD HTML 4.0 Transitional/EN", "HTML"];
+
+const QUIRKS_SYSTEM_ID: &str = "http://www.ibm.com/data/dtd/v11/ibmxhtml1-transitional.dtd";
+
+const QUIRKS_PUBLIC_ID_PREFIXES: [&str; 55] = [
+	"+//Silmaril//dtd html Pro v0r11 19970101//",
+	"-//AS//DTD HTML 3.0 asWedit + extensions//",
+	"-//AdvaSoft Ltd//DTD HTML 3.0 asWedit + extensions//",
+	"-//IETF//DTD HTML 2.0 Level 1//",
+	"-//IETF//DTD HTML 2.0 Level 2//",
+	"-//IETF//DTD HTML 2.0 Strict Level 1//",
+	"-//IETF//DTD HTML 2.0 Strict Level 2//",
+	"-//IETF//DTD HTML 2.0 Strict//",
+	"-//IETF//DTD HTML 2.0//",
+	"-//IETF//DTD HTML 2.1E//",
+	"-//IETF//DTD HTML 3.0//",
+	"-//IETF//DTD HTML 3.2 Final//",
+	"-//IETF//DTD HTML 3.2//",
+	"-//IETF//DTD HTML 3//",
+	"-//IETF//DTD HTML Level 0//",
+	"-//IETF//DTD HTML Level 1//",
+	"-//IETF//DTD HTML Level 2//",
+	"-//IETF//DTD HTML Level 3//",
+	"-//IETF//DTD HTML Strict Level 0//",
+	"-//IETF//DTD HTML Strict Level 1//",
+	"-//IETF//DTD HTML Strict Level 2//",
+	"-//IETF//DTD HTML Strict Level 3//",
+	"-//IETF//DTD HTML Strict//",
+	"-//IETF//DTD HTML//",
+	"-//Metrius//DTD Metrius Presentational//",
+	"-//Microsoft//DTD Internet Explorer 2.0 HTML Strict//",
+	"-//Microsoft//DTD Internet Explorer 2.0 HTML//",
+	"-//Microsoft//DTD Internet Explorer 2.0 Tables//",
+	"-//Microsoft//DTD Internet Explorer 3.0 HTML Strict//",
+	"-//Microsoft//DTD Internet Explorer 3.0 HTML//",
+	"-//Microsoft//DTD Internet Explorer 3.0 Tables//",
+	"-//Netscape Comm. Corp.//DTD HTML//",
+	"-//Netscape Comm. Corp.//DTD Strict HTML//",
+	"-//O'Reilly and Associates//DTD HTML 2.0//",
+	"-//O'Reilly and Associates//DTD HTML Extended 1.0//",
+	"-//O'Reilly and Associates//DTD HTML Extended Relaxed 1.0//",
+	"-//SQ//DTD HTML 2.0 HoTMetaL + extensions//",
+	"-//SoftQuad Software//DTD HoTMetaL PRO 6.0::19990601::extensions to HTML 4.0//",
+	"-//SoftQuad//DTD HoTMetaL PRO 4.0::19971010::extensions to HTML 4.0//",
+	"-//Spyglass//DTD HTML 2.0 Extended//",
+	"-//Sun Microsystems Corp.//DTD HotJava HTML//",
+	"-//Sun Microsystems Corp.//DTD HotJava Strict HTML//",
+	"-//W3C//DTD HTML 3 1995-03-24//",
+	"-//W3C//DTD HTML 3.2 Draft//",
+	"-//W3C//DTD HTML 3.2 Final//",
+	"-//W3C//DTD HTML 3.2//",
+	"-//W3C//DTD HTML 3.2S Draft//",
+	"-//W3C//DTD HTML 4.0 Frameset//",
+	"-//W3C//DTD HTML 4.0 Transitional//",
+	"-//W3C//DTD HTML Experimental 19960712//",
+	"-//W3C//DTD HTML Experimental 970421//",
+	"-//W3C//DTD W3 HTML//",
+	"-//W3O//DTD W3 HTML 3.0//",
+	"-//WebTechs//DTD Mozilla HTML 2.0//",
+	"-//WebTechs//DTD Mozilla HTML//",
+];
+
+/// HTML 4.01 documents are in quirks mode without a system identifier and in
+/// limited-quirks mode with one.
+const HTML_401_PUBLIC_ID_PREFIXES: [&str; 2] =
+	["-//W3C//DTD HTML 4.01 Frameset//", "-//W3C//DTD HTML 4.01 Transitional//"];
+
+const LIMITED_QUIRKS_PUBLIC_ID_PREFIXES: [&str; 2] =
+	["-//W3C//DTD XHTML 1.0 Frameset//", "-//W3C//DTD XHTML 1.0 Transitional//"];
+
+#[derive(Clone, Copy)]
+enum Scope {
+	Default,
+	ListItem,
+	Button,
+	Table,
+}
+
+impl TreeBuilder {
+	pub fn new(tokenizer: Tokenizer) -> TreeBuilder {
+		TreeBuilder {
+			tokenizer,
+			nodes: Vec::new(),
+			document_children: Vec::new(),
+			open_elements: Vec::new(),
+			active_formatting: Vec::new(),
+			mode: InsertionMode::Initial,
+			original_mode: InsertionMode::Initial,
+			quirks_mode: dom::QuirksMode::NoQuirks,
+			head: None,
+			form: None,
+			frameset_ok: true,
+			foster_parenting: false,
+			pending_table_characters: Vec::new(),
+			ignore_next_line_feed: false,
+			token_span: Span::default(),
+			errors: Vec::new(),
+		}
+	}
+
+	/// Runs the tokenizer to completion and returns the document along with
+	/// every tokenizer and tree construction error.
+	pub fn build(self) -> (dom::Node, Vec<ParseError>) {
+		self.finish()
+	}
+
+	/// Feeds the next chunk of a streaming tokenizer's input, and builds as
+	/// much of the tree as it can.
+	pub fn push_str(&mut self, chunk: &str) {
+		self.tokenizer.push_str(chunk);
+		self.run();
+	}
+
+	/// Ends the input and returns the finished document, like `build`.
+	pub fn finish(mut self) -> (dom::Node, Vec<ParseError>) {
+		self.tokenizer.finish();
+		self.run();
+		let mut errors = self.tokenizer.take_errors();
+		errors.append(&mut self.errors);
+		errors.sort_by_key(|error| error.span().start.offset);
+		(self.document(), errors)
+	}
+
+	/// A copy of the document as built so far. Elements that haven't been
+	/// closed yet have everything parsed up to now as their children.
+	pub fn document(&self) -> dom::Node {
+		let mut children: Vec<dom::Node> = self.document_children.iter().map(|&child| self.dom_node(child)).collect();
+		if !children.iter().any(|child| matches!(child.node_type, dom::NodeType::Element(_))) {
+			children.push(dom::element("html".to_string(), dom::AttrMap::new(), Vec::new()));
+		}
+		dom::document(self.quirks_mode, children)
+	}
+
+	/// Processes tokens until the tokenizer runs out of input.
+	fn run(&mut self) {
+		while let Some((token, span)) = self.tokenizer.next_token() {
+			self.token_span = span;
+			self.process(token);
+			let in_foreign_content = self
+				.open_elements
+				.last()
+				.is_some_and(|&current| self.nodes[current].namespace != Namespace::Html);
+			self.tokenizer.set_in_foreign_content(in_foreign_content);
+		}
+	}
+
+	fn dom_node(&self, id: NodeId) -> dom::Node {
+		// As in `Document::to_node`, each node is copied after its children,
+		// which are the last copies made.
+		let mut copies: Vec<dom::Node> = Vec::new();
+		let mut unvisited = vec![(id, false)];
+		while let Some((id, children_copied)) = unvisited.pop() {
+			let node = &self.nodes[id];
+			if !children_copied {
+				unvisited.push((id, true));
+				unvisited.extend(node.children.iter().rev().map(|&child| (child, false)));
+				continue;
+			}
+			let children = copies.split_off(copies.len() - node.children.len());
+			copies.push(dom::Node {
+				children,
+				node_type: node.node_type.clone(),
+				span: node.span.clone(),
+			});
+		}
+		copies.pop().expect("the root is always copied")
+	}
+
+	fn error(&mut self, code: &'static str) {
+		self.errors.push(ParseError::Syntax { code, span: self.token_span.clone() });
+	}
+
+	fn process(&mut self, token: Token) {
+		if self.ignore_next_line_feed {
+			self.ignore_next_line_feed = false;
+			if token == Token::Character('\n') {
+				return;
+			}
+		}
+		if self.in_foreign_content(&token) {
+			self.process_foreign_content(token);
+		} else {
+			self.process_in(self.mode, token);
+		}
+	}
+
+	fn process_in(&mut self, mode: InsertionMode, token: Token) {
+		match mode {
+			InsertionMode::Initial => self.initial(token),
+			InsertionMode::BeforeHtml => self.before_html(token),
+			InsertionMode::BeforeHead => self.before_head(token),
+			InsertionMode::InHead => self.in_head(token),
+			InsertionMode::InHeadNoscript => self.in_head_noscript(token),
+			InsertionMode::AfterHead => self.after_head(token),
+			InsertionMode::InBody => self.in_body(token),
+			InsertionMode::Text => self.text(token),
+			InsertionMode::InTable => self.in_table(token),
+			InsertionMode::InTableText => self.in_table_text(token),
+			InsertionMode::InCaption => self.in_caption(token),
+			InsertionMode::InColumnGroup => self.in_column_group(token),
+			InsertionMode::InTableBody => self.in_table_body(token),
+			InsertionMode::InRow => self.in_row(token),
+			InsertionMode::InCell => self.in_cell(token),
+			InsertionMode::AfterBody => self.after_body(token),
+			InsertionMode::AfterAfterBody => self.after_after_body(token),
+		}
+	}
+
+	fn reprocess_in(&mut self, mode: InsertionMode, token: Token) {
+		self.mode = mode;
+		self.process(token);
+	}
+
+	fn initial(&mut self, token: Token) {
+		match token {
+			Token::Character(c) if is_html_whitespace(c) => {}
+			Token::Comment(comment) => self.insert_comment_into_document(comment),
+			Token::Doctype(doctype) => {
+				let name = doctype.name.as_deref();
+				let system_id = doctype.system_id.as_deref();
+				if name != Some("html")
+					|| doctype.public_id.is_some()
+					|| system_id.is_some_and(|system_id| system_id != "about:legacy-compat")
+				{
+					self.error("unknown-doctype");
+				}
+				self.quirks_mode = quirks_mode(&doctype);
+				let doctype = dom::DoctypeData {
+					name: doctype.name.unwrap_or_default(),
+					public_id: doctype.public_id.unwrap_or_default(),
+					system_id: doctype.system_id.unwrap_or_default(),
+				};
+				let node = self.create_node(dom::NodeType::Doctype(doctype), Namespace::Html);
+				self.document_children.push(node);
+				self.mode = InsertionMode::BeforeHtml;
+			}
+			token => {
+				self.error("missing-doctype");
+				self.quirks_mode = dom::QuirksMode::Quirks;
+				self.reprocess_in(InsertionMode::BeforeHtml, token);
+			}
+		}
+	}
+
+	fn before_html(&mut self, token: Token) {
+		match token {
+			Token::Doctype(_) => self.error("unexpected-doctype"),
+			Token::Comment(comment) => self.insert_comment_into_document(comment),
+			Token::Character(c) if is_html_whitespace(c) => {}
+			Token::StartTag(tag) if tag.name == "html" => {
+				let html = self.create_element(&tag, Namespace::Html);
+				self.document_children.push(html);
+				self.open_elements.push(html);
+				self.mode = InsertionMode::BeforeHead;
+			}
+			Token::EndTag(ref tag) if !matches!(tag.name.as_str(), "head" | "body" | "html" | "br") => {
+				self.error("unexpected-end-tag");
+			}
+			token => {
+				let html = self.create_element(&synthetic_tag("html"), Namespace::Html);
+				self.document_children.push(html);
+				self.open_elements.push(html);
+				self.reprocess_in(InsertionMode::BeforeHead, token);
+			}
+		}
+	}
+
+	fn before_head(&mut self, token: Token) {
+		match token {
+			Token::Character(c) if is_html_whitespace(c) => {}
+			Token::Comment(comment) => self.insert_comment(comment),
+			Token::Doctype(_) => self.error("unexpected-doctype"),
+			Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+			Token::StartTag(tag) if tag.name == "head" => {
+				self.head = Some(self.insert_html_element(&tag));
+				self.mode = InsertionMode::InHead;
+			}
+			Token::EndTag(ref tag) if !matches!(tag.name.as_str(), "head" | "body" | "html" | "br") => {
+				self.error("unexpected-end-tag");
+			}
+			token => {
+				self.head = Some(self.insert_html_element(&synthetic_tag("head")));
+				self.reprocess_in(InsertionMode::InHead, token);
+			}
+		}
+	}
+
+	fn in_head(&mut self, token: Token) {
+		match token {
+			Token::Character(c) if is_html_whitespace(c) => self.insert_character(c),
+			Token::Comment(comment) => self.insert_comment(comment),
+			Token::Doctype(_) => self.error("unexpected-doctype"),
+			Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+			Token::StartTag(tag) if matches!(tag.name.as_str(), "base" | "basefont" | "bgsound" | "link" | "meta") => {
+				self.insert_html_element(&tag);
+				self.pop();
+			}
+			Token::StartTag(tag) if tag.name == "title" => self.parse_text_element(&tag, tokenizer::State::Rcdata),
+			Token::StartTag(tag) if matches!(tag.name.as_str(), "noframes" | "style") => {
+				self.parse_text_element(&tag, tokenizer::State::Rawtext);
+			}
+			Token::StartTag(tag) if tag.name == "script" => {
+				self.parse_text_element(&tag, tokenizer::State::ScriptData);
+			}
+			Token::StartTag(tag) if tag.name == "noscript" => {
+				self.insert_html_element(&tag);
+				self.mode = InsertionMode::InHeadNoscript;
+			}
+			Token::StartTag(ref tag) if tag.name == "head" => self.error("unexpected-start-tag"),
+			Token::EndTag(ref tag) if tag.name == "head" => {
+				self.pop_closing();
+				self.mode = InsertionMode::AfterHead;
+			}
+			Token::EndTag(ref tag) if !matches!(tag.name.as_str(), "body" | "html" | "br") => {
+				self.error("unexpected-end-tag");
+			}
+			token => {
+				self.pop();
+				self.reprocess_in(InsertionMode::AfterHead, token);
+			}
+		}
+	}
+
+	fn in_head_noscript(&mut self, token: Token) {
+		match token {
+			Token::Doctype(_) => self.error("unexpected-doctype"),
+			Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+			Token::EndTag(ref tag) if tag.name == "noscript" => {
+				self.pop_closing();
+				self.mode = InsertionMode::InHead;
+			}
+			Token::Character(c) if is_html_whitespace(c) => self.in_head(token),
+			Token::Comment(_) => self.in_head(token),
+			Token::StartTag(ref tag)
+				if matches!(tag.name.as_str(), "basefont" | "bgsound" | "link" | "meta" | "noframes" | "style") =>
+			{
+				self.in_head(token)
+			}
+			Token::StartTag(ref tag) if matches!(tag.name.as_str(), "head" | "noscript") => {
+				self.error("unexpected-start-tag");
+			}
+			Token::EndTag(ref tag) if tag.name != "br" => self.error("unexpected-end-tag"),
+			token => {
+				self.error("unexpected-token-in-noscript");
+				self.pop();
+				self.reprocess_in(InsertionMode::InHead, token);
+			}
+		}
+	}
+
+	fn after_head(&mut self, token: Token) {
+		match token {
+			Token::Character(c) if is_html_whitespace(c) => self.insert_character(c),
+			Token::Comment(comment) => self.insert_comment(comment),
+			Token::Doctype(_) => self.error("unexpected-doctype"),
+			Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+			Token::StartTag(tag) if matches!(tag.name.as_str(), "body" | "frameset") => {
+				self.insert_html_element(&tag);
+				self.frameset_ok = false;
+				self.mode = InsertionMode::InBody;
+			}
+			Token::StartTag(ref tag)
+				if matches!(
+					tag.name.as_str(),
+					"base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script" | "style" | "title"
+				) =>
+			{
+				self.error("unexpected-start-tag");
+				let Some(head) = self.head else {
+					return;
+				};
+				self.open_elements.push(head);
+				self.in_head(token);
+				self.open_elements.retain(|&id| id != head);
+			}
+			Token::StartTag(ref tag) if tag.name == "head" => self.error("unexpected-start-tag"),
+			Token::EndTag(ref tag) if !matches!(tag.name.as_str(), "body" | "html" | "br") => {
+				self.error("unexpected-end-tag");
+			}
+			token => {
+				self.insert_html_element(&synthetic_tag("body"));
+				self.reprocess_in(InsertionMode::InBody, token);
+			}
+		}
+	}
+
+	fn in_body(&mut self, token: Token) {
+		match token {
+			Token::Character('\0') => self.error("unexpected-null-character"),
+			Token::Character(c) => {
+				self.reconstruct_active_formatting_elements();
+				self.insert_character(c);
+				if !is_html_whitespace(c) {
+					self.frameset_ok = false;
+				}
+			}
+			Token::Comment(comment) => self.insert_comment(comment),
+			Token::Doctype(_) => self.error("unexpected-doctype"),
+			Token::StartTag(tag) => self.start_tag_in_body(tag),
+			Token::EndTag(tag) => self.end_tag_in_body(tag),
+			Token::EndOfFile => self.stop_parsing(),
+		}
+	}
+
+	fn start_tag_in_body(&mut self, tag: TagToken) {
+		let name = tag.name.as_str();
+		match name {
+			"html" => {
+				self.error("unexpected-start-tag");
+				if let Some(&html) = self.open_elements.first() {
+					self.merge_attributes(html, &tag);
+				}
+			}
+			"base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script" | "style" | "title" => {
+				self.in_head(Token::StartTag(tag));
+			}
+			"body" => {
+				self.error("unexpected-start-tag");
+				if let Some(&body) = self.open_elements.get(1) {
+					if self.is_html_element(body, "body") {
+						self.frameset_ok = false;
+						self.merge_attributes(body, &tag);
+					}
+				}
+			}
+			// Framesets aren't supported, so a stray one is dropped.
+			"frameset" => self.error("unexpected-start-tag"),
+			_ if BLOCK_ELEMENTS.contains(&name) => {
+				self.close_p_in_button_scope();
+				self.insert_html_element(&tag);
+			}
+			_ if HEADINGS.contains(&name) => {
+				self.close_p_in_button_scope();
+				if HEADINGS.iter().any(|heading| self.current_node_is(heading)) {
+					self.error("nested-heading");
+					self.pop();
+				}
+				self.insert_html_element(&tag);
+			}
+			"pre" | "listing" => {
+				self.close_p_in_button_scope();
+				self.insert_html_element(&tag);
+				self.ignore_next_line_feed = true;
+				self.frameset_ok = false;
+			}
+			"form" => {
+				if self.form.is_some() {
+					self.error("nested-form");
+					return;
+				}
+				self.close_p_in_button_scope();
+				self.form = Some(self.insert_html_element(&tag));
+			}
+			"li" | "dd" | "dt" => {
+				self.frameset_ok = false;
+				let closes: &[&str] = if name == "li" { &["li"] } else { &["dd", "dt"] };
+				for index in (0..self.open_elements.len()).rev() {
+					let node = self.open_elements[index];
+					if let Some(&closed) = closes.iter().find(|closed| self.is_html_element(node, closed)) {
+						self.generate_implied_end_tags(Some(closed));
+						if !self.current_node_is(closed) {
+							self.error("unexpected-open-element");
+						}
+						self.pop_until_tag(closed);
+						break;
+					}
+					if self.is_special(node) && !["address", "div", "p"].iter().any(|n| self.is_html_element(node, n)) {
+						break;
+					}
+				}
+				self.close_p_in_button_scope();
+				self.insert_html_element(&tag);
+			}
+			"plaintext" => {
+				self.close_p_in_button_scope();
+				self.insert_html_element(&tag);
+				self.tokenizer.set_state(tokenizer::State::Plaintext);
+			}
+			"button" => {
+				if self.has_in_scope("button", Scope::Default) {
+					self.error("nested-button");
+					self.generate_implied_end_tags(None);
+					self.pop_until_tag("button");
+				}
+				self.reconstruct_active_formatting_elements();
+				self.insert_html_element(&tag);
+				self.frameset_ok = false;
+			}
+			"a" => {
+				let existing = self.formatting_elements_after_last_marker().find(|&(id, _)| self.is_html_element(id, "a"));
+				if let Some((existing, _)) = existing {
+					self.error("nested-anchor");
+					self.adoption_agency("a");
+					self.active_formatting.retain(|entry| !matches!(entry, FormattingEntry::Element(id, _) if *id == existing));
+					self.open_elements.retain(|&id| id != existing);
+				}
+				self.reconstruct_active_formatting_elements();
+				let element = self.insert_html_element(&tag);
+				self.push_active_formatting_element(element, tag);
+			}
+			"nobr" => {
+				self.reconstruct_active_formatting_elements();
+				if self.has_in_scope("nobr", Scope::Default) {
+					self.error("nested-nobr");
+					self.adoption_agency("nobr");
+					self.reconstruct_active_formatting_elements();
+				}
+				let element = self.insert_html_element(&tag);
+				self.push_active_formatting_element(element, tag);
+			}
+			_ if FORMATTING_ELEMENTS.contains(&name) => {
+				self.reconstruct_active_formatting_elements();
+				let element = self.insert_html_element(&tag);
+				self.push_active_formatting_element(element, tag);
+			}
+			"applet" | "marquee" | "object" => {
+				self.reconstruct_active_formatting_elements();
+				self.insert_html_element(&tag);
+				self.active_formatting.push(FormattingEntry::Marker);
+				self.frameset_ok = false;
+			}
+			"table" => {
+				if self.quirks_mode != dom::QuirksMode::Quirks {
+					self.close_p_in_button_scope();
+				}
+				self.insert_html_element(&tag);
+				self.frameset_ok = false;
+				self.mode = InsertionMode::InTable;
+			}
+			"area" | "br" | "embed" | "img" | "keygen" | "wbr" | "input" => {
+				self.reconstruct_active_formatting_elements();
+				self.insert_html_element(&tag);
+				self.pop_closing();
+				let hidden_input = name == "input"
+					&& tag.attributes.iter().any(|(name, value)| name == "type" && value.eq_ignore_ascii_case("hidden"));
+				if !hidden_input {
+					self.frameset_ok = false;
+				}
+			}
+			"param" | "source" | "track" => {
+				self.insert_html_element(&tag);
+				self.pop_closing();
+			}
+			"hr" => {
+				self.close_p_in_button_scope();
+				self.insert_html_element(&tag);
+				self.pop_closing();
+				self.frameset_ok = false;
+			}
+			"image" => {
+				self.error("image-start-tag");
+				self.start_tag_in_body(TagToken { name: "img".to_string(), ..tag });
+			}
+			"textarea" => {
+				self.parse_text_element(&tag, tokenizer::State::Rcdata);
+				self.ignore_next_line_feed = true;
+				self.frameset_ok = false;
+			}
+			"xmp" | "iframe" | "noembed" => {
+				if name == "xmp" {
+					self.close_p_in_button_scope();
+					self.reconstruct_active_formatting_elements();
+				}
+				self.frameset_ok = false;
+				self.parse_text_element(&tag, tokenizer::State::Rawtext);
+			}
+			"optgroup" | "option" => {
+				if self.current_node_is("option") {
+					self.pop();
+				}
+				self.reconstruct_active_formatting_elements();
+				self.insert_html_element(&tag);
+			}
+			"rb" | "rtc" => {
+				if self.has_in_scope("ruby", Scope::Default) {
+					self.generate_implied_end_tags(None);
+				}
+				self.insert_html_element(&tag);
+			}
+			"rp" | "rt" => {
+				if self.has_in_scope("ruby", Scope::Default) {
+					self.generate_implied_end_tags(Some("rtc"));
+				}
+				self.insert_html_element(&tag);
+			}
+			"math" | "svg" => {
+				self.reconstruct_active_formatting_elements();
+				let namespace = if name == "math" { Namespace::MathMl } else { Namespace::Svg };
+				self.insert_element(&tag, namespace);
+				if tag.self_closing {
+					self.pop_closing();
+				}
+			}
+			"caption" | "col" | "colgroup" | "frame" | "head" | "tbody" | "td" | "tfoot" | "th" | "thead" | "tr" => {
+				self.error("unexpected-start-tag");
+			}
+			_ => {
+				self.reconstruct_active_formatting_elements();
+				self.insert_html_element(&tag);
+			}
+		}
+	}
+
+	fn end_tag_in_body(&mut self, tag: TagToken) {
+		let name = tag.name.as_str();
+		match name {
+			"body" | "html" => {
+				if !self.has_in_scope("body", Scope::Default) {
+					self.error("unexpected-end-tag");
+					return;
+				}
+				self.mode = InsertionMode::AfterBody;
+				if name == "html" {
+					self.process(Token::EndTag(tag));
+				}
+			}
+			_ if (BLOCK_ELEMENTS.contains(&name) && name != "p") || matches!(name, "button" | "pre" | "listing") => {
+				if !self.has_in_scope(name, Scope::Default) {
+					self.error("unexpected-end-tag");
+					return;
+				}
+				self.generate_implied_end_tags(None);
+				if !self.current_node_is(name) {
+					self.error("unexpected-end-tag");
+				}
+				self.pop_until_tag(name);
+			}
+			"form" => {
+				let Some(form) = self.form.take() else {
+					self.error("unexpected-end-tag");
+					return;
+				};
+				if !self.has_element_in_scope(form, Scope::Default) {
+					self.error("unexpected-end-tag");
+					return;
+				}
+				self.generate_implied_end_tags(None);
+				if self.open_elements.last() != Some(&form) {
+					self.error("unexpected-end-tag");
+				}
+				self.open_elements.retain(|&id| id != form);
+				self.close_span(form);
+			}
+			"p" => {
+				if !self.has_in_scope("p", Scope::Button) {
+					self.error("unexpected-end-tag");
+					self.insert_html_element(&synthetic_tag("p"));
+				}
+				self.close_p_element();
+			}
+			"li" | "dd" | "dt" => {
+				let scope = if name == "li" { Scope::ListItem } else { Scope::Default };
+				if !self.has_in_scope(name, scope) {
+					self.error("unexpected-end-tag");
+					return;
+				}
+				self.generate_implied_end_tags(Some(name));
+				if !self.current_node_is(name) {
+					self.error("unexpected-end-tag");
+				}
+				self.pop_until_tag(name);
+			}
+			_ if HEADINGS.contains(&name) => {
+				if !HEADINGS.iter().any(|heading| self.has_in_scope(heading, Scope::Default)) {
+					self.error("unexpected-end-tag");
+					return;
+				}
+				self.generate_implied_end_tags(None);
+				if !self.current_node_is(name) {
+					self.error("unexpected-end-tag");
+				}
+				while let Some(node) = self.pop_closing() {
+					if HEADINGS.iter().any(|heading| self.is_html_element(node, heading)) {
+						break;
+					}
+				}
+			}
+			_ if FORMATTING_ELEMENTS.contains(&name) => self.adoption_agency(name),
+			"applet" | "marquee" | "object" => {
+				if !self.has_in_scope(name, Scope::Default) {
+					self.error("unexpected-end-tag");
+					return;
+				}
+				self.generate_implied_end_tags(None);
+				if !self.current_node_is(name) {
+					self.error("unexpected-end-tag");
+				}
+				self.pop_until_tag(name);
+				self.clear_active_formatting_to_last_marker();
+			}
+			"br" => {
+				self.error("unexpected-end-tag");
+				self.start_tag_in_body(synthetic_tag("br"));
+			}
+			_ => self.any_other_end_tag(name),
+		}
+	}
+
+	fn any_other_end_tag(&mut self, name: &str) {
+		for index in (0..self.open_elements.len()).rev() {
+			let node = self.open_elements[index];
+			if self.is_html_element(node, name) {
+				self.generate_implied_end_tags(Some(name));
+				if self.open_elements.last() != Some(&node) {
+					self.error("unexpected-end-tag");
+				}
+				while let Some(popped) = self.pop_closing() {
+					if popped == node {
+						break;
+					}
+				}
+				return;
+			}
+			if self.is_special(node) {
+				self.error("unexpected-end-tag");
+				return;
+			}
+		}
+	}
+
+	/// The text insertion mode, used for the contents of elements like
+	/// `title` and `style` until their end tag.
+	fn text(&mut self, token: Token) {
+		match token {
+			Token::Character(c) => self.insert_character(c),
+			Token::EndTag(ref tag) if self.current_node_is(&tag.name) => {
+				self.pop_closing();
+				self.mode = self.original_mode;
+			}
+			Token::EndOfFile => {
+				self.error("eof-in-element");
+				self.pop();
+				self.reprocess_in(self.original_mode, token);
+			}
+			_ => self.error("unexpected-token-in-text"),
+		}
+	}
+
+	fn in_table(&mut self, token: Token) {
+		match token {
+			Token::Character(_) if self.open_elements.last().is_some_and(|&current| self.is_table_context(current)) => {
+				self.pending_table_characters.clear();
+				self.original_mode = self.mode;
+				self.reprocess_in(InsertionMode::InTableText, token);
+			}
+			Token::Comment(comment) => self.insert_comment(comment),
+			Token::Doctype(_) => self.error("unexpected-doctype"),
+			Token::StartTag(tag) if tag.name == "caption" => {
+				self.clear_stack_back_to(&["table", "template", "html"]);
+				self.active_formatting.push(FormattingEntry::Marker);
+				self.insert_html_element(&tag);
+				self.mode = InsertionMode::InCaption;
+			}
+			Token::StartTag(tag) if tag.name == "colgroup" => {
+				self.clear_stack_back_to(&["table", "template", "html"]);
+				self.insert_html_element(&tag);
+				self.mode = InsertionMode::InColumnGroup;
+			}
+			Token::StartTag(ref tag) if tag.name == "col" => {
+				self.clear_stack_back_to(&["table", "template", "html"]);
+				self.insert_html_element(&synthetic_tag("colgroup"));
+				self.reprocess_in(InsertionMode::InColumnGroup, token);
+			}
+			Token::StartTag(tag) if matches!(tag.name.as_str(), "tbody" | "tfoot" | "thead") => {
+				self.clear_stack_back_to(&["table", "template", "html"]);
+				self.insert_html_element(&tag);
+				self.mode = InsertionMode::InTableBody;
+			}
+			Token::StartTag(ref tag) if matches!(tag.name.as_str(), "td" | "th" | "tr") => {
+				self.clear_stack_back_to(&["table", "template", "html"]);
+				self.insert_html_element(&synthetic_tag("tbody"));
+				self.reprocess_in(InsertionMode::InTableBody, token);
+			}
+			Token::StartTag(ref tag) if tag.name == "table" => {
+				self.error("unexpected-start-tag");
+				if self.has_in_scope("table", Scope::Table) {
+					self.pop_until_one_of(&["table"]);
+					self.reset_insertion_mode();
+					self.process(token);
+				}
+			}
+			Token::EndTag(ref tag) if tag.name == "table" => {
+				if !self.has_in_scope("table", Scope::Table) {
+					self.error("unexpected-end-tag");
+					return;
+				}
+				self.pop_until_tag("table");
+				self.reset_insertion_mode();
+			}
+			Token::EndTag(ref tag)
+				if matches!(
+					tag.name.as_str(),
+					"body" | "caption" | "col" | "colgroup" | "html" | "tbody" | "td" | "tfoot" | "th" | "thead" | "tr"
+				) =>
+			{
+				self.error("unexpected-end-tag");
+			}
+			Token::StartTag(ref tag) if matches!(tag.name.as_str(), "script" | "style") => self.in_head(token),
+			Token::StartTag(tag)
+				if tag.name == "input"
+					&& tag.attributes.iter().any(|(name, value)| name == "type" && value.eq_ignore_ascii_case("hidden")) =>
+			{
+				self.error("unexpected-start-tag");
+				self.insert_html_element(&tag);
+				self.pop_closing();
+			}
+			Token::StartTag(tag) if tag.name == "form" => {
+				self.error("unexpected-start-tag");
+				if self.form.is_none() {
+					self.form = Some(self.insert_html_element(&tag));
+					self.pop_closing();
+				}
+			}
+			Token::EndOfFile => self.in_body(token),
+			token => {
+				self.error("unexpected-token-in-table");
+				self.foster_parenting = true;
+				self.in_body(token);
+				self.foster_parenting = false;
+			}
+		}
+	}
+
+	/// Collects the characters that appear directly in a table, so they can
+	/// be kept in place if they're all whitespace and foster parented if not.
+	fn in_table_text(&mut self, token: Token) {
+		match token {
+			Token::Character('\0') => self.error("unexpected-null-character"),
+			Token::Character(c) => self.pending_table_characters.push((c, self.token_span.clone())),
+			token => {
+				let characters = std::mem::take(&mut self.pending_table_characters);
+				let token_span = self.token_span.clone();
+				if characters.iter().any(|&(c, _)| !is_html_whitespace(c)) {
+					self.error("unexpected-character-in-table");
+					self.foster_parenting = true;
+					for (c, span) in characters {
+						self.token_span = span;
+						self.in_body(Token::Character(c));
+					}
+					self.foster_parenting = false;
+				} else {
+					for (c, span) in characters {
+						self.token_span = span;
+						self.insert_character(c);
+					}
+				}
+				self.token_span = token_span;
+				self.reprocess_in(self.original_mode, token);
+			}
+		}
+	}
+
+	fn in_caption(&mut self, token: Token) {
+		match token {
+			Token::EndTag(ref tag) if tag.name == "caption" => {
+				if !self.has_in_scope("caption", Scope::Table) {
+					self.error("unexpected-end-tag");
+					return;
+				}
+				self.generate_implied_end_tags(None);
+				if !self.current_node_is("caption") {
+					self.error("unexpected-end-tag");
+				}
+				self.pop_until_tag("caption");
+				self.clear_active_formatting_to_last_marker();
+				self.mode = InsertionMode::InTable;
+			}
+			Token::StartTag(ref tag)
+				if matches!(
+					tag.name.as_str(),
+					"caption" | "col" | "colgroup" | "tbody" | "td" | "tfoot" | "th" | "thead" | "tr"
+				) =>
+			{
+				self.close_caption_and_reprocess(token);
+			}
+			Token::EndTag(ref tag) if tag.name == "table" => self.close_caption_and_reprocess(token),
+			Token::EndTag(ref tag)
+				if matches!(
+					tag.name.as_str(),
+					"body" | "col" | "colgroup" | "html" | "tbody" | "td" | "tfoot" | "th" | "thead" | "tr"
+				) =>
+			{
+				self.error("unexpected-end-tag");
+			}
+			token => self.in_body(token),
+		}
+	}
+
+	fn close_caption_and_reprocess(&mut self, token: Token) {
+		if !self.has_in_scope("caption", Scope::Table) {
+			self.error("unexpected-token-in-caption");
+			return;
+		}
+		self.generate_implied_end_tags(None);
+		if !self.current_node_is("caption") {
+			self.error("unexpected-open-element");
+		}
+		self.pop_until_one_of(&["caption"]);
+		self.clear_active_formatting_to_last_marker();
+		self.reprocess_in(InsertionMode::InTable, token);
+	}
+
+	fn in_column_group(&mut self, token: Token) {
+		match token {
+			Token::Character(c) if is_html_whitespace(c) => self.insert_character(c),
+			Token::Comment(comment) => self.insert_comment(comment),
+			Token::Doctype(_) => self.error("unexpected-doctype"),
+			Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+			Token::StartTag(tag) if tag.name == "col" => {
+				self.insert_html_element(&tag);
+				self.pop_closing();
+			}
+			Token::EndTag(ref tag) if tag.name == "colgroup" => {
+				if !self.current_node_is("colgroup") {
+					self.error("unexpected-end-tag");
+					return;
+				}
+				self.pop_closing();
+				self.mode = InsertionMode::InTable;
+			}
+			Token::EndTag(ref tag) if tag.name == "col" => self.error("unexpected-end-tag"),
+			Token::EndOfFile => self.in_body(token),
+			token => {
+				if !self.current_node_is("colgroup") {
+					self.error("unexpected-token-in-column-group");
+					return;
+				}
+				self.pop();
+				self.reprocess_in(InsertionMode::InTable, token);
+			}
+		}
+	}
+
+	fn in_table_body(&mut self, token: Token) {
+		match token {
+			Token::StartTag(tag) if tag.name == "tr" => {
+				self.clear_stack_back_to(&["tbody", "tfoot", "thead", "template", "html"]);
+				self.insert_html_element(&tag);
+				self.mode = InsertionMode::InRow;
+			}
+			Token::StartTag(ref tag) if matches!(tag.name.as_str(), "td" | "th") => {
+				self.error("unexpected-cell-in-table-body");
+				self.clear_stack_back_to(&["tbody", "tfoot", "thead", "template", "html"]);
+				self.insert_html_element(&synthetic_tag("tr"));
+				self.reprocess_in(InsertionMode::InRow, token);
+			}
+			Token::EndTag(ref tag) if matches!(tag.name.as_str(), "tbody" | "tfoot" | "thead") => {
+				if !self.has_in_scope(&tag.name, Scope::Table) {
+					self.error("unexpected-end-tag");
+					return;
+				}
+				if let Some(section) = self.close_table_section() {
+					self.close_span(section);
+				}
+			}
+			Token::StartTag(ref tag)
+				if matches!(tag.name.as_str(), "caption" | "col" | "colgroup" | "tbody" | "tfoot" | "thead") =>
+			{
+				self.close_table_section_and_reprocess(token);
+			}
+			Token::EndTag(ref tag) if tag.name == "table" => self.close_table_section_and_reprocess(token),
+			Token::EndTag(ref tag)
+				if matches!(tag.name.as_str(), "body" | "caption" | "col" | "colgroup" | "html" | "td" | "th" | "tr") =>
+			{
+				self.error("unexpected-end-tag");
+			}
+			token => self.in_table(token),
+		}
+	}
+
+	/// Closes the open `tbody`, `thead` or `tfoot` and returns it.
+	fn close_table_section(&mut self) -> Option<NodeId> {
+		self.clear_stack_back_to(&["tbody", "tfoot", "thead", "template", "html"]);
+		self.mode = InsertionMode::InTable;
+		self.pop()
+	}
+
+	fn close_table_section_and_reprocess(&mut self, token: Token) {
+		if !["tbody", "tfoot", "thead"].iter().any(|name| self.has_in_scope(name, Scope::Table)) {
+			self.error("unexpected-token-in-table-body");
+			return;
+		}
+		self.close_table_section();
+		self.process(token);
+	}
+
+	fn in_row(&mut self, token: Token) {
+		match token {
+			Token::StartTag(tag) if matches!(tag.name.as_str(), "td" | "th") => {
+				self.clear_stack_back_to(&["tr", "template", "html"]);
+				self.insert_html_element(&tag);
+				self.mode = InsertionMode::InCell;
+				self.active_formatting.push(FormattingEntry::Marker);
+			}
+			Token::EndTag(ref tag) if tag.name == "tr" => {
+				if !self.has_in_scope("tr", Scope::Table) {
+					self.error("unexpected-end-tag");
+					return;
+				}
+				if let Some(row) = self.close_row() {
+					self.close_span(row);
+				}
+			}
+			Token::StartTag(ref tag)
+				if matches!(tag.name.as_str(), "caption" | "col" | "colgroup" | "tbody" | "tfoot" | "thead" | "tr") =>
+			{
+				self.close_row_and_reprocess(token);
+			}
+			Token::EndTag(ref tag) if tag.name == "table" => self.close_row_and_reprocess(token),
+			Token::EndTag(ref tag) if matches!(tag.name.as_str(), "tbody" | "tfoot" | "thead") => {
+				if !self.has_in_scope(&tag.name, Scope::Table) {
+					self.error("unexpected-end-tag");
+					return;
+				}
+				self.close_row_and_reprocess(token);
+			}
+			Token::EndTag(ref tag)
+				if matches!(tag.name.as_str(), "body" | "caption" | "col" | "colgroup" | "html" | "td" | "th") =>
+			{
+				self.error("unexpected-end-tag");
+			}
+			token => self.in_table(token),
+		}
+	}
+
+	/// Closes the open `tr` and returns it.
+	fn close_row(&mut self) -> Option<NodeId> {
+		self.clear_stack_back_to(&["tr", "template", "html"]);
+		self.mode = InsertionMode::InTableBody;
+		self.pop()
+	}
+
+	fn close_row_and_reprocess(&mut self, token: Token) {
+		if !self.has_in_scope("tr", Scope::Table) {
+			self.error("unexpected-token-in-row");
+			return;
+		}
+		self.close_row();
+		self.process(token);
+	}
+
+	fn in_cell(&mut self, token: Token) {
+		match token {
+			Token::EndTag(ref tag) if matches!(tag.name.as_str(), "td" | "th") => {
+				if !self.has_in_scope(&tag.name, Scope::Table) {
+					self.error("unexpected-end-tag");
+					return;
+				}
+				self.generate_implied_end_tags(None);
+				if !self.current_node_is(&tag.name) {
+					self.error("unexpected-end-tag");
+				}
+				self.pop_until_tag(&tag.name);
+				self.clear_active_formatting_to_last_marker();
+				self.mode = InsertionMode::InRow;
+			}
+			Token::StartTag(ref tag)
+				if matches!(
+					tag.name.as_str(),
+					"caption" | "col" | "colgroup" | "tbody" | "td" | "tfoot" | "th" | "thead" | "tr"
+				) =>
+			{
+				if !self.has_in_scope("td", Scope::Table) && !self.has_in_scope("th", Scope::Table) {
+					self.error("unexpected-start-tag");
+					return;
+				}
+				self.close_cell();
+				self.process(token);
+			}
+			Token::EndTag(ref tag) if matches!(tag.name.as_str(), "body" | "caption" | "col" | "colgroup" | "html") => {
+				self.error("unexpected-end-tag");
+			}
+			Token::EndTag(ref tag) if matches!(tag.name.as_str(), "table" | "tbody" | "tfoot" | "thead" | "tr") => {
+				if !self.has_in_scope(&tag.name, Scope::Table) {
+					self.error("unexpected-end-tag");
+					return;
+				}
+				self.close_cell();
+				self.process(token);
+			}
+			token => self.in_body(token),
+		}
+	}
+
+	/// Closes the open `td` or `th` because some later token implied its end.
+	fn close_cell(&mut self) {
+		self.generate_implied_end_tags(None);
+		if !self.current_node_is("td") && !self.current_node_is("th") {
+			self.error("unexpected-open-element");
+		}
+		self.pop_until_one_of(&["td", "th"]);
+		self.clear_active_formatting_to_last_marker();
+		self.mode = InsertionMode::InRow;
+	}
+
+	/// Picks the insertion mode from the stack of open elements, after the
+	/// end of a table.
+	fn reset_insertion_mode(&mut self) {
+		for (index, &node) in self.open_elements.iter().enumerate().rev() {
+			let last = index == 0;
+			let name = if self.nodes[node].namespace == Namespace::Html { self.tag_name(node) } else { "" };
+			self.mode = match name {
+				"td" | "th" if !last => InsertionMode::InCell,
+				"tr" => InsertionMode::InRow,
+				"tbody" | "tfoot" | "thead" => InsertionMode::InTableBody,
+				"caption" => InsertionMode::InCaption,
+				"colgroup" => InsertionMode::InColumnGroup,
+				"table" => InsertionMode::InTable,
+				"head" if !last => InsertionMode::InHead,
+				"body" => InsertionMode::InBody,
+				"html" if self.head.is_none() => InsertionMode::BeforeHead,
+				"html" => InsertionMode::AfterHead,
+				_ if last => InsertionMode::InBody,
+				_ => continue,
+			};
+			return;
+		}
+		self.mode = InsertionMode::InBody;
+	}
+
+	fn after_body(&mut self, token: Token) {
+		match token {
+			Token::Character(c) if is_html_whitespace(c) => self.in_body(token),
+			Token::Comment(comment) => {
+				if let Some(&html) = self.open_elements.first() {
+					let node = self.create_node(dom::NodeType::Comment(comment), Namespace::Html);
+					self.append_child(html, node);
+				}
+			}
+			Token::Doctype(_) => self.error("unexpected-doctype"),
+			Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+			Token::EndTag(ref tag) if tag.name == "html" => self.mode = InsertionMode::AfterAfterBody,
+			Token::EndOfFile => self.stop_parsing(),
+			token => {
+				self.error("content-after-body");
+				self.reprocess_in(InsertionMode::InBody, token);
+			}
+		}
+	}
+
+	fn after_after_body(&mut self, token: Token) {
+		match token {
+			Token::Comment(comment) => self.insert_comment_into_document(comment),
+			Token::Doctype(_) => self.in_body(token),
+			Token::Character(c) if is_html_whitespace(c) => self.in_body(token),
+			Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+			Token::EndOfFile => self.stop_parsing(),
+			token => {
+				self.error("content-after-html");
+				self.reprocess_in(InsertionMode::InBody, token);
+			}
+		}
+	}
+
+	fn in_foreign_content(&self, token: &Token) -> bool {
+		let Some(&current) = self.open_elements.last() else {
+			return false;
+		};
+		let namespace = self.nodes[current].namespace;
+		if namespace == Namespace::Html || *token == Token::EndOfFile {
+			return false;
+		}
+		let name = self.tag_name(current);
+		match token {
+			Token::StartTag(tag) => {
+				let mathml_text_point = self.is_mathml_text_integration_point(current)
+					&& !matches!(tag.name.as_str(), "mglyph" | "malignmark");
+				let svg_in_annotation = namespace == Namespace::MathMl && name == "annotation-xml" && tag.name == "svg";
+				!(mathml_text_point || svg_in_annotation || self.is_html_integration_point(current))
+			}
+			Token::Character(_) => {
+				!(self.is_mathml_text_integration_point(current) || self.is_html_integration_point(current))
+			}
+			_ => true,
+		}
+	}
+
+	fn process_foreign_content(&mut self, token: Token) {
+		match token {
+			Token::Character('\0') => {
+				self.error("unexpected-null-character");
+				self.insert_character('\u{FFFD}');
+			}
+			Token::Character(c) => {
+				self.insert_character(c);
+				if !is_html_whitespace(c) {
+					self.frameset_ok = false;
+				}
+			}
+			Token::Comment(comment) => self.insert_comment(comment),
+			Token::Doctype(_) => self.error("unexpected-doctype"),
+			Token::StartTag(ref tag)
+				if FOREIGN_BREAKOUT_ELEMENTS.contains(&tag.name.as_str())
+					|| tag.name == "font"
+						&& tag.attributes.iter().any(|(name, _)| matches!(name.as_str(), "color" | "face" | "size")) =>
+			{
+				self.error("html-start-tag-in-foreign-content");
+				while let Some(&current) = self.open_elements.last() {
+					if self.nodes[current].namespace == Namespace::Html
+						|| self.is_html_integration_point(current)
+						|| self.is_mathml_text_integration_point(current)
+					{
+						break;
+					}
+					self.pop();
+				}
+				self.process_in(self.mode, token);
+			}
+			Token::StartTag(tag) => {
+				let namespace = self.open_elements.last().map_or(Namespace::Html, |&id| self.nodes[id].namespace);
+				self.insert_element(&tag, namespace);
+				if tag.self_closing {
+					self.pop_closing();
+				}
+			}
+			Token::EndTag(tag) => {
+				if self.open_elements.last().is_some_and(|&current| !self.tag_name(current).eq_ignore_ascii_case(&tag.name)) {
+					self.error("unexpected-end-tag");
+				}
+				for index in (1..self.open_elements.len()).rev() {
+					let node = self.open_elements[index];
+					if self.tag_name(node).eq_ignore_ascii_case(&tag.name) {
+						while let Some(popped) = self.pop_closing() {
+							if popped == node {
+								break;
+							}
+						}
+						return;
+					}
+					if self.nodes[self.open_elements[index - 1]].namespace == Namespace::Html {
+						self.process_in(self.mode, Token::EndTag(tag));
+						return;
+					}
+				}
+			}
+			Token::EndOfFile => self.process_in(self.mode, token),
+		}
+	}
+
+	fn is_mathml_text_integration_point(&self, id: NodeId) -> bool {
+		self.nodes[id].namespace == Namespace::MathMl && MATHML_TEXT_INTEGRATION_POINTS.contains(&self.tag_name(id))
+	}
+
+	fn is_html_integration_point(&self, id: NodeId) -> bool {
+		let name = self.tag_name(id);
+		match self.nodes[id].namespace {
+			Namespace::Svg => SVG_HTML_INTEGRATION_POINTS.contains(&name),
+			Namespace::MathMl => {
+				name == "annotation-xml"
+					&& self.attribute(id, "encoding").is_some_and(|encoding| {
+						encoding.eq_ignore_ascii_case("text/html") || encoding.eq_ignore_ascii_case("application/xhtml+xml")
+					})
+			}
+			Namespace::Html => false,
+		}
+	}
+
+	/// The generic RCDATA and raw text element parsing algorithms: the
+	/// tokenizer switches to `state`, so the element's contents come back as
+	/// text until its end tag.
+	fn parse_text_element(&mut self, tag: &TagToken, state: tokenizer::State) {
+		self.insert_html_element(tag);
+		self.tokenizer.set_state(state);
+		self.original_mode = self.mode;
+		self.mode = InsertionMode::Text;
+	}
+
+	fn stop_parsing(&mut self) {
+		let unexpected_open = self.open_elements.iter().any(|&id| {
+			!matches!(
+				self.tag_name(id),
+				"dd" | "dt" | "li" | "optgroup" | "option" | "p" | "rb" | "rp" | "rt" | "rtc" | "tbody" | "td"
+					| "tfoot" | "th" | "thead" | "tr" | "body" | "html"
+			)
+		});
+		if unexpected_open {
+			self.error("eof-with-open-elements");
+		}
+		while self.pop().is_some() {}
+	}
+
+	fn adoption_agency(&mut self, subject: &str) {
+		if let Some(&current) = self.open_elements.last() {
+			if self.is_html_element(current, subject) && !self.is_active_formatting_element(current) {
+				self.pop_closing();
+				return;
+			}
+		}
+
+		for _ in 0..8 {
+			let formatting = self
+				.formatting_elements_after_last_marker()
+				.find(|&(id, _)| self.is_html_element(id, subject))
+				.map(|(id, tag)| (id, tag.clone()));
+			let Some((formatting_element, formatting_tag)) = formatting else {
+				self.any_other_end_tag(subject);
+				return;
+			};
+
+			let Some(formatting_index) = self.open_elements.iter().position(|&id| id == formatting_element) else {
+				self.error("misnested-formatting-element");
+				self.remove_active_formatting_element(formatting_element);
+				return;
+			};
+			if !self.has_element_in_scope(formatting_element, Scope::Default) {
+				self.error("misnested-formatting-element");
+				return;
+			}
+			if self.open_elements.last() != Some(&formatting_element) {
+				self.error("misnested-formatting-element");
+			}
+
+			let furthest_block = self.open_elements[formatting_index + 1..]
+				.iter()
+				.copied()
+				.find(|&id| self.is_special(id));
+			let Some(furthest_block) = furthest_block else {
+				while let Some(popped) = self.pop_closing() {
+					if popped == formatting_element {
+						break;
+					}
+				}
+				self.remove_active_formatting_element(formatting_element);
+				return;
+			};
+
+			let common_ancestor = self.open_elements[formatting_index - 1];
+			let mut bookmark = self.active_formatting_position(formatting_element).unwrap_or_default();
+			let mut node_index = self.open_elements.iter().position(|&id| id == furthest_block).unwrap_or_default();
+			let mut last_node = furthest_block;
+
+			let mut inner_loop_counter = 0;
+			loop {
+				inner_loop_counter += 1;
+				node_index -= 1;
+				let mut node = self.open_elements[node_index];
+				if node == formatting_element {
+					break;
+				}
+				if inner_loop_counter > 3 && self.is_active_formatting_element(node) {
+					self.remove_active_formatting_element(node);
+				}
+				let Some(position) = self.active_formatting_position(node) else {
+					self.open_elements.remove(node_index);
+					continue;
+				};
+
+				let tag = match self.active_formatting[position] {
+					FormattingEntry::Element(_, ref tag) => tag.clone(),
+					FormattingEntry::Marker => break,
+				};
+				let replacement = self.create_element(&tag, Namespace::Html);
+				self.active_formatting[position] = FormattingEntry::Element(replacement, tag);
+				self.open_elements[node_index] = replacement;
+				node = replacement;
+
+				if last_node == furthest_block {
+					bookmark = position + 1;
+				}
+				self.append_child(node, last_node);
+				last_node = node;
+			}
+
+			self.insert_at(self.insertion_place(common_ancestor), last_node);
+
+			let replacement = self.create_element(&formatting_tag, Namespace::Html);
+			let children = std::mem::take(&mut self.nodes[furthest_block].children);
+			for child in children {
+				self.nodes[child].parent = None;
+				self.append_child(replacement, child);
+			}
+			self.append_child(furthest_block, replacement);
+
+			if let Some(position) = self.active_formatting_position(formatting_element) {
+				self.active_formatting.remove(position);
+				if position < bookmark {
+					bookmark -= 1;
+				}
+			}
+			let bookmark = bookmark.min(self.active_formatting.len());
+			self.active_formatting.insert(bookmark, FormattingEntry::Element(replacement, formatting_tag));
+
+			self.open_elements.retain(|&id| id != formatting_element);
+			let furthest_block_index = self.open_elements.iter().position(|&id| id == furthest_block).unwrap_or_default();
+			self.open_elements.insert(furthest_block_index + 1, replacement);
+		}
+	}
+
+	fn formatting_elements_after_last_marker(&self) -> impl Iterator<Item = (NodeId, &TagToken)> {
+		self.active_formatting
+			.iter()
+			.rev()
+			.take_while(|entry| !matches!(entry, FormattingEntry::Marker))
+			.filter_map(|entry| match entry {
+				FormattingEntry::Element(id, tag) => Some((*id, tag)),
+				FormattingEntry::Marker => None,
+			})
+	}
+
+	fn active_formatting_position(&self, element: NodeId) -> Option<usize> {
+		self.active_formatting
+			.iter()
+			.position(|entry| matches!(entry, FormattingEntry::Element(id, _) if *id == element))
+	}
+
+	fn is_active_formatting_element(&self, element: NodeId) -> bool {
+		self.active_formatting_position(element).is_some()
+	}
+
+	fn remove_active_formatting_element(&mut self, element: NodeId) {
+		if let Some(position) = self.active_formatting_position(element) {
+			self.active_formatting.remove(position);
+		}
+	}
+
+	/// Pushes onto the list of active formatting elements, keeping at most
+	/// three identical elements after the last marker.
+	fn push_active_formatting_element(&mut self, element: NodeId, tag: TagToken) {
+		let identical: Vec<NodeId> = self
+			.formatting_elements_after_last_marker()
+			.filter(|(_, existing)| existing.name == tag.name && same_attributes(existing, &tag))
+			.map(|(id, _)| id)
+			.collect();
+		if identical.len() >= 3 {
+			if let Some(&earliest) = identical.last() {
+				self.remove_active_formatting_element(earliest);
+			}
+		}
+		self.active_formatting.push(FormattingEntry::Element(element, tag));
+	}
+
+	fn reconstruct_active_formatting_elements(&mut self) {
+		let is_open = |builder: &TreeBuilder, entry: &FormattingEntry| match entry {
+			FormattingEntry::Marker => true,
+			FormattingEntry::Element(id, _) => builder.open_elements.contains(id),
+		};
+		let Some(last) = self.active_formatting.last() else {
+			return;
+		};
+		if is_open(self, last) {
+			return;
+		}
+		let mut index = self.active_formatting.len() - 1;
+		while index > 0 && !is_open(self, &self.active_formatting[index - 1]) {
+			index -= 1;
+		}
+		for position in index..self.active_formatting.len() {
+			let FormattingEntry::Element(_, ref tag) = self.active_formatting[position] else {
+				continue;
+			};
+			let tag = tag.clone();
+			let element = self.insert_html_element(&tag);
+			self.active_formatting[position] = FormattingEntry::Element(element, tag);
+		}
+	}
+
+	fn clear_active_formatting_to_last_marker(&mut self) {
+		while let Some(entry) = self.active_formatting.pop() {
+			if let FormattingEntry::Marker = entry {
+				break;
+			}
+		}
+	}
+
+	fn has_in_scope(&self, name: &str, scope: Scope) -> bool {
+		self.find_in_scope(|builder, id| builder.is_html_element(id, name), scope)
+	}
+
+	fn has_element_in_scope(&self, element: NodeId, scope: Scope) -> bool {
+		self.find_in_scope(|_, id| id == element, scope)
+	}
+
+	fn find_in_scope<F>(&self, target: F, scope: Scope) -> bool
+	where
+		F: Fn(&TreeBuilder, NodeId) -> bool,
+	{
+		for &id in self.open_elements.iter().rev() {
+			if target(self, id) {
+				return true;
+			}
+			if self.is_scope_boundary(id, scope) {
+				return false;
+			}
+		}
+		false
+	}
+
+	fn is_scope_boundary(&self, id: NodeId, scope: Scope) -> bool {
+		let name = self.tag_name(id);
+		match self.nodes[id].namespace {
+			Namespace::Html => match scope {
+				Scope::Default => SCOPE_BOUNDARIES.contains(&name),
+				Scope::ListItem => SCOPE_BOUNDARIES.contains(&name) || matches!(name, "ol" | "ul"),
+				Scope::Button => SCOPE_BOUNDARIES.contains(&name) || name == "button",
+				Scope::Table => matches!(name, "html" | "table" | "template"),
+			},
+			Namespace::MathMl => {
+				!matches!(scope, Scope::Table)
+					&& (MATHML_TEXT_INTEGRATION_POINTS.contains(&name) || name == "annotation-xml")
+			}
+			Namespace::Svg => !matches!(scope, Scope::Table) && SVG_HTML_INTEGRATION_POINTS.contains(&name),
+		}
+	}
+
+	fn generate_implied_end_tags(&mut self, except: Option<&str>) {
+		while let Some(&current) = self.open_elements.last() {
+			let name = self.tag_name(current);
+			if self.nodes[current].namespace != Namespace::Html
+				|| !IMPLIED_END_TAGS.contains(&name)
+				|| Some(name) == except
+			{
+				break;
+			}
+			self.pop();
+		}
+	}
+
+	fn close_p_in_button_scope(&mut self) {
+		if self.has_in_scope("p", Scope::Button) {
+			self.close_p_element();
+		}
+	}
+
+	fn close_p_element(&mut self) {
+		self.generate_implied_end_tags(Some("p"));
+		if !self.current_node_is("p") {
+			self.error("unexpected-open-element");
+		}
+		self.pop_until_tag("p");
+	}
+
+	fn pop_until_tag(&mut self, name: &str) {
+		while let Some(popped) = self.pop_closing() {
+			if self.is_html_element(popped, name) {
+				break;
+			}
+		}
+	}
+
+	/// Pops up to and including the first of `names`, because some later
+	/// token implied their end.
+	fn pop_until_one_of(&mut self, names: &[&str]) {
+		while let Some(popped) = self.pop() {
+			if names.iter().any(|name| self.is_html_element(popped, name)) {
+				break;
+			}
+		}
+	}
+
+	/// Pops until the current node is one of `context`, which always
+	/// includes `html`.
+	fn clear_stack_back_to(&mut self, context: &[&str]) {
+		while !context.iter().any(|name| self.current_node_is(name)) {
+			if self.pop().is_none() {
+				break;
+			}
+		}
+	}
+
+	/// Pops the current node because some later token implied its end.
+	fn pop(&mut self) -> Option<NodeId> {
+		let id = self.open_elements.pop()?;
+		self.nodes[id].span.end = self.token_span.start;
+		Some(id)
+	}
+
+	/// Pops the current node because the current token closes it.
+	fn pop_closing(&mut self) -> Option<NodeId> {
+		let id = self.open_elements.pop()?;
+		self.close_span(id);
+		Some(id)
+	}
+
+	fn close_span(&mut self, id: NodeId) {
+		self.nodes[id].span.end = self.token_span.end;
+	}
+
+	fn current_node_is(&self, name: &str) -> bool {
+		self.open_elements.last().is_some_and(|&id| self.is_html_element(id, name))
+	}
+
+	fn is_html_element(&self, id: NodeId, name: &str) -> bool {
+		self.nodes[id].namespace == Namespace::Html && self.tag_name(id) == name
+	}
+
+	fn is_table_context(&self, id: NodeId) -> bool {
+		self.nodes[id].namespace == Namespace::Html && TABLE_CONTEXTS.contains(&self.tag_name(id))
+	}
+
+	fn is_special(&self, id: NodeId) -> bool {
+		let name = self.tag_name(id);
+		match self.nodes[id].namespace {
+			Namespace::Html => SPECIAL_ELEMENTS.contains(&name),
+			Namespace::MathMl => MATHML_TEXT_INTEGRATION_POINTS.contains(&name) || name == "annotation-xml",
+			Namespace::Svg => SVG_HTML_INTEGRATION_POINTS.contains(&name),
+		}
+	}
+
+	fn tag_name(&self, id: NodeId) -> &str {
+		match self.nodes[id].node_type {
+			dom::NodeType::Element(ref element) => &element.tag_name,
+			_ => "",
+		}
+	}
+
+	fn attribute(&self, id: NodeId, name: &str) -> Option<&String> {
+		match self.nodes[id].node_type {
+			dom::NodeType::Element(ref element) => element.attributes.get(name),
+			_ => None,
+		}
+	}
+
+	fn merge_attributes(&mut self, id: NodeId, tag: &TagToken) {
+		if let dom::NodeType::Element(ref mut element) = self.nodes[id].node_type {
+			for (name, value) in &tag.attributes {
+				if !element.attributes.contains_key(name) {
+					element.attributes.insert(name.clone(), value.clone());
+				}
+			}
+		}
+	}
+
+	fn create_node(&mut self, node_type: dom::NodeType, namespace: Namespace) -> NodeId {
+		self.nodes.push(BuilderNode {
+			node_type,
+			namespace,
+			parent: None,
+			children: Vec::new(),
+			span: self.token_span.clone(),
+		});
+		self.nodes.len() - 1
+	}
+
+	fn create_element(&mut self, tag: &TagToken, namespace: Namespace) -> NodeId {
+		let element = dom::ElementData { tag_name: tag.name.clone(), attributes: tag.attributes.iter().cloned().collect() };
+		self.create_node(dom::NodeType::Element(element), namespace)
+	}
+
+	fn append_child(&mut self, parent: NodeId, child: NodeId) {
+		if let Some(old_parent) = self.nodes[child].parent {
+			self.nodes[old_parent].children.retain(|&id| id != child);
+		}
+		self.nodes[child].parent = Some(parent);
+		self.nodes[parent].children.push(child);
+	}
+
+	/// The appropriate place for inserting a node into `target`: a parent,
+	/// and the child to insert before if it isn't appended. With foster
+	/// parenting on, nodes meant for a table go just before it instead.
+	fn insertion_place(&self, target: NodeId) -> (NodeId, Option<NodeId>) {
+		if !self.foster_parenting || !self.is_table_context(target) {
+			return (target, None);
+		}
+		let Some(index) = self.open_elements.iter().rposition(|&id| self.is_html_element(id, "table")) else {
+			return (self.open_elements[0], None);
+		};
+		let table = self.open_elements[index];
+		match self.nodes[table].parent {
+			Some(parent) => (parent, Some(table)),
+			None => (self.open_elements[index - 1], None),
+		}
+	}
+
+	fn insert_at(&mut self, (parent, before): (NodeId, Option<NodeId>), child: NodeId) {
+		self.append_child(parent, child);
+		if let Some(before) = before {
+			let children = &mut self.nodes[parent].children;
+			if let Some(index) = children.iter().position(|&id| id == before) {
+				children.pop();
+				children.insert(index, child);
+			}
+		}
+	}
+
+	fn insert_element(&mut self, tag: &TagToken, namespace: Namespace) -> NodeId {
+		let element = self.create_element(tag, namespace);
+		if let Some(&target) = self.open_elements.last() {
+			self.insert_at(self.insertion_place(target), element);
+		}
+		self.open_elements.push(element);
+		element
+	}
+
+	fn insert_html_element(&mut self, tag: &TagToken) -> NodeId {
+		self.insert_element(tag, Namespace::Html)
+	}
+
+	fn insert_character(&mut self, c: char) {
+		let Some(&target) = self.open_elements.last() else {
+			return;
+		};
+		let (parent, before) = self.insertion_place(target);
+		let end = self.token_span.end;
+		let children = &self.nodes[parent].children;
+		let previous = match before {
+			Some(before) => children.iter().position(|&id| id == before).and_then(|index| index.checked_sub(1)),
+			None => children.len().checked_sub(1),
+		};
+		if let Some(previous) = previous.map(|index| children[index]) {
+			if let dom::NodeType::Text(ref mut text) = self.nodes[previous].node_type {
+				text.push(c);
+				self.nodes[previous].span.end = end;
+				return;
+			}
+		}
+		let text = self.create_node(dom::NodeType::Text(c.to_string()), Namespace::Html);
+		self.insert_at((parent, before), text);
+	}
+
+	fn insert_comment(&mut self, comment: String) {
+		let node = self.create_node(dom::NodeType::Comment(comment), Namespace::Html);
+		match self.open_elements.last() {
+			Some(&target) => self.insert_at(self.insertion_place(target), node),
+			None => self.document_children.push(node),
+		}
+	}
+
+	fn insert_comment_into_document(&mut self, comment: String) {
+		let node = self.create_node(dom::NodeType::Comment(comment), Namespace::Html);
+		self.document_children.push(node);
+	}
+}
+
+/// Decides the quirks mode from the DOCTYPE, mostly by recognising the
+/// public identifiers of legacy HTML versions.
+fn quirks_mode(doctype: &DoctypeToken) -> dom::QuirksMode {
+	let public_id = doctype.public_id.as_deref();
+	let system_id = doctype.system_id.as_deref();
+	let public_id_is = |ids: &[&str]| public_id.is_some_and(|public_id| ids.iter().any(|id| id.eq_ignore_ascii_case(public_id)));
+	let public_id_starts_with = |prefixes: &[&str]| {
+		public_id.is_some_and(|public_id| {
+			prefixes.iter().any(|prefix| public_id.get(..prefix.len()).is_some_and(|start| start.eq_ignore_ascii_case(prefix)))
+		})
+	};
+
+	if doctype.force_quirks
+		|| doctype.name.as_deref() != Some("html")
+		|| public_id_is(&QUIRKS_PUBLIC_IDS)
+		|| system_id.is_some_and(|system_id| system_id.eq_ignore_ascii_case(QUIRKS_SYSTEM_ID))
+		|| public_id_starts_with(&QUIRKS_PUBLIC_ID_PREFIXES)
+		|| (system_id.is_none() && public_id_starts_with(&HTML_401_PUBLIC_ID_PREFIXES))
+	{
+		dom::QuirksMode::Quirks
+	} else if public_id_starts_with(&LIMITED_QUIRKS_PUBLIC_ID_PREFIXES)
+		|| (system_id.is_some() && public_id_starts_with(&HTML_401_PUBLIC_ID_PREFIXES))
+	{
+		dom::QuirksMode::LimitedQuirks
+	} else {
+		dom::QuirksMode::NoQuirks
+	}
+}
+
+fn synthetic_tag(name: &str) -> TagToken {
+	TagToken { name: name.to_string(), ..TagToken::default() }
+}
+
+fn same_attributes(a: &TagToken, b: &TagToken) -> bool {
+	a.attributes.len() == b.attributes.len()
+		&& a.attributes.iter().all(|attribute| b.attributes.contains(attribute))
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::parse::{dom, html};
+
+	/// Prints a parsed document the way html5lib's tree construction tests
+	/// write their expected trees.
+	fn tree(source: &str) -> String {
+		let document = html::parse(source.to_string());
+		let mut lines = Vec::new();
+		let mut unvisited: Vec<(&dom::Node, usize)> = document.children.iter().rev().map(|child| (child, 0)).collect();
+		while let Some((node, depth)) = unvisited.pop() {
+			let indent = "  ".repeat(depth);
+			match node.node_type {
+				dom::NodeType::Element(ref element) => {
+					lines.push(format!("| {}<{}>", indent, element.tag_name));
+					for (name, value) in element.attributes.iter() {
+						lines.push(format!("| {}  {}=\"{}\"", indent, name, value));
+					}
+				}
+				dom::NodeType::Text(ref text) => lines.push(format!("| {}\"{}\"", indent, text)),
+				dom::NodeType::Comment(ref comment) => lines.push(format!("| {}<!-- {} -->", indent, comment)),
+				dom::NodeType::Doctype(ref doctype) => lines.push(format!("| {}<!DOCTYPE {}>", indent, doctype.name)),
+				dom::NodeType::Document(_) => {}
+			}
+			unvisited.extend(node.children.iter().rev().map(|child| (child, depth + 1)));
+		}
+		lines.join("\n")
+	}
+
+	fn assert_tree(source: &str, expected: &str) {
+		let expected: Vec<&str> = expected.lines().map(str::trim).filter(|line| !line.is_empty()).collect();
+		assert_eq!(tree(source), expected.join("\n"), "parsing {:?}", source);
+	}
+
+	#[test]
+	fn p_start_tag_closes_open_p() {
+		assert_tree(
+			"<p>one<p>two",
+			r#"
+			| <html>
+			|   <head>
+			|   <body>
+			|     <p>
+			|       "one"
+			|     <p>
+			|       "two"
+			"#,
+		);
+	}
+
+	#[test]
+	fn li_start_tag_closes_open_li() {
+		assert_tree(
+			"<ul><li>one<li>two</ul>",
+			r#"
+			| <html>
+			|   <head>
+			|   <body>
+			|     <ul>
+			|       <li>
+			|         "one"
+			|       <li>
+			|         "two"
+			"#,
+		);
+	}
+
+	#[test]
+	fn elements_open_at_eof_are_closed() {
+		assert_tree(
+			"<div><span>text",
+			r#"
+			| <html>
+			|   <head>
+			|   <body>
+			|     <div>
+			|       <span>
+			|         "text"
+			"#,
+		);
+	}
+
+	#[test]
+	fn misnested_formatting_elements() {
+		assert_tree(
+			"<b>1<i>2</b>3</i>",
+			r#"
+			| <html>
+			|   <head>
+			|   <body>
+			|     <b>
+			|       "1"
+			|       <i>
+			|         "2"
+			|     <i>
+			|       "3"
+			"#,
+		);
+	}
+
+	#[test]
+	fn formatting_element_around_block() {
+		assert_tree(
+			"<b>1<p>2</b>3</p>",
+			r#"
+			| <html>
+			|   <head>
+			|   <body>
+			|     <b>
+			|       "1"
+			|     <p>
+			|       <b>
+			|         "2"
+			|       "3"
+			"#,
+		);
+	}
+
+	#[test]
+	fn html_head_and_body_are_implied() {
+		assert_tree(
+			"<!DOCTYPE html><title>t</title>text",
+			r#"
+			| <!DOCTYPE html>
+			| <html>
+			|   <head>
+			|     <title>
+			|       "t"
+			|   <body>
+			|     "text"
+			"#,
+		);
+	}
+
+	#[test]
+	fn table_end_tag_closes_cells() {
+		assert_tree(
+			"<table><tr><td>a<td>b<tr><td>c</table><p>after",
+			r#"
+			| <html>
+			|   <head>
+			|   <body>
+			|     <table>
+			|       <tbody>
+			|         <tr>
+			|           <td>
+			|             "a"
+			|           <td>
+			|             "b"
+			|         <tr>
+			|           <td>
+			|             "c"
+			|     <p>
+			|       "after"
+			"#,
+		);
+	}
+
+	#[test]
+	fn text_and_elements_in_tables_are_foster_parented() {
+		assert_tree(
+			"<table>a<b>bold</b> c<tr><td>cell</table>",
+			r#"
+			| <html>
+			|   <head>
+			|   <body>
+			|     "a"
+			|     <b>
+			|       "bold"
+			|     " c"
+			|     <table>
+			|       <tbody>
+			|         <tr>
+			|           <td>
+			|             "cell"
+			"#,
+		);
+	}
+
+	#[test]
+	fn whitespace_in_tables_stays_in_place() {
+		assert_tree(
+			"<table> <caption>c</caption><col> <tr><th>h</table>",
+			r#"
+			| <html>
+			|   <head>
+			|   <body>
+			|     <table>
+			|       " "
+			|       <caption>
+			|         "c"
+			|       <colgroup>
+			|         <col>
+			|         " "
+			|       <tbody>
+			|         <tr>
+			|           <th>
+			|             "h"
+			"#,
+		);
+	}
+
+	#[test]
+	fn tables_nest_in_cells() {
+		assert_tree(
+			"<table><td>a<table><td>b</table>c</td></tr></table>d",
+			r#"
+			| <html>
+			|   <head>
+			|   <body>
+			|     <table>
+			|       <tbody>
+			|         <tr>
+			|           <td>
+			|             "a"
+			|             <table>
+			|               <tbody>
+			|                 <tr>
+			|                   <td>
+			|                     "b"
+			|             "c"
+			|     "d"
+			"#,
+		);
+	}
+
+	#[test]
+	fn formatting_elements_are_foster_parented_around_cells() {
+		assert_tree(
+			"<table><a>1<td>2</td>3</table>",
+			r#"
+			| <html>
+			|   <head>
+			|   <body>
+			|     <a>
+			|       "1"
+			|     <a>
+			|       "3"
+			|     <table>
+			|       <tbody>
+			|         <tr>
+			|           <td>
+			|             "2"
+			"#,
+		);
+	}
+
+	#[test]
+	fn listing_drops_leading_newline() {
+		assert_tree(
+			"<listing>\nx</listing>",
+			r#"
+			| <html>
+			|   <head>
+			|   <body>
+			|     <listing>
+			|       "x"
+			"#,
+		);
+	}
+}
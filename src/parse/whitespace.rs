@@ -0,0 +1,166 @@
+use crate::parse::{cssom::Value, dom::NodeType, style::StyledNode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WhiteSpace {
+	Normal,
+	Pre,
+	Nowrap,
+	PreWrap,
+	PreLine,
+	BreakSpaces,
+}
+
+impl WhiteSpace {
+	fn from_keyword(keyword: &str) -> Option<WhiteSpace> {
+		match &*keyword.to_ascii_lowercase() {
+			"normal" => Some(WhiteSpace::Normal),
+			"pre" => Some(WhiteSpace::Pre),
+			"nowrap" => Some(WhiteSpace::Nowrap),
+			"pre-wrap" => Some(WhiteSpace::PreWrap),
+			"pre-line" => Some(WhiteSpace::PreLine),
+			"break-spaces" => Some(WhiteSpace::BreakSpaces),
+			_ => None,
+		}
+	}
+
+	/// The default for elements the user agent stylesheet gives a
+	/// `white-space` to.
+	fn for_element(tag_name: &str) -> Option<WhiteSpace> {
+		match tag_name {
+			"pre" | "listing" | "xmp" | "plaintext" => Some(WhiteSpace::Pre),
+			"textarea" => Some(WhiteSpace::PreWrap),
+			_ => None,
+		}
+	}
+
+	fn collapses_spaces(self) -> bool {
+		matches!(self, WhiteSpace::Normal | WhiteSpace::Nowrap | WhiteSpace::PreLine)
+	}
+
+	fn preserves_newlines(self) -> bool {
+		!matches!(self, WhiteSpace::Normal | WhiteSpace::Nowrap)
+	}
+}
+
+/// Elements the user agent stylesheet hides.
+const HIDDEN_ELEMENTS: [&str; 11] =
+	["area", "base", "datalist", "head", "link", "meta", "param", "rp", "script", "style", "title"];
+
+/// Elements the user agent stylesheet lays out as blocks.
+const BLOCK_ELEMENTS: [&str; 48] = [
+	"address", "article", "aside", "blockquote", "body", "center", "dd", "details", "dialog", "dir", "div",
+	"dl", "dt", "fieldset", "figcaption", "figure", "footer", "form", "h1", "h2", "h3", "h4", "h5", "h6",
+	"header", "hgroup", "hr", "html", "legend", "li", "listing", "main", "menu", "nav", "ol", "optgroup",
+	"p", "plaintext", "pre", "search", "section", "summary", "table", "td", "th", "tr", "ul", "xmp",
+];
+
+/// Sets the text each text node renders, following the white space
+/// processing rules of CSS Text: unless `white-space` preserves them, runs
+/// of spaces, tabs and newlines collapse to one space, and spaces at the
+/// start or end of a line are removed. Every block starts a new line.
+pub fn collapse(root: &mut StyledNode) {
+	let mut collapser = Collapser { line_start: true, pending_space: false };
+	let mut steps = vec![Step::Visit(root, WhiteSpace::Normal, true)];
+	while let Some(step) = steps.pop() {
+		match step {
+			Step::Visit(node, inherited, rendered) => collapser.visit(node, inherited, rendered, &mut steps),
+			Step::BreakLine => collapser.break_line(),
+		}
+	}
+}
+
+/// Work still to do in document order: a node to visit with the white space
+/// it inherits and whether it's rendered, or the line break a block ends
+/// with once its children are done.
+enum Step<'a, 'b> {
+	Visit(&'a mut StyledNode<'b>, WhiteSpace, bool),
+	BreakLine,
+}
+
+struct Collapser {
+	line_start: bool,
+	/// A collapsed space that is only kept if more text follows on the same
+	/// line.
+	pending_space: bool,
+}
+
+impl Collapser {
+	/// Processes a node, leaving what has to be done for its children on
+	/// `steps`.
+	fn visit<'a, 'b>(
+		&mut self,
+		node: &'a mut StyledNode<'b>,
+		inherited: WhiteSpace,
+		rendered: bool,
+		steps: &mut Vec<Step<'a, 'b>>,
+	) {
+		let dom_node = node.node;
+		match dom_node.node_type {
+			NodeType::Text(ref text) => {
+				node.text = match rendered {
+					true => Some(self.process(text, inherited)).filter(|text| !text.is_empty()),
+					false => None,
+				};
+			}
+			NodeType::Element(ref element) => {
+				let display = match node.specified_values.get("display") {
+					Some(Value::Keyword(display)) => display.to_ascii_lowercase(),
+					_ if HIDDEN_ELEMENTS.contains(&element.tag_name.as_str()) => "none".to_string(),
+					_ if BLOCK_ELEMENTS.contains(&element.tag_name.as_str()) => "block".to_string(),
+					_ => "inline".to_string(),
+				};
+				let white_space = match node.specified_values.get("white-space") {
+					Some(Value::Keyword(keyword)) => WhiteSpace::from_keyword(keyword),
+					_ => None,
+				};
+				let white_space = white_space.or(WhiteSpace::for_element(&element.tag_name)).unwrap_or(inherited);
+				let rendered = rendered && display != "none";
+				let breaks_line = rendered && (display != "inline" || element.tag_name == "br");
+
+				if breaks_line {
+					self.break_line();
+					steps.push(Step::BreakLine);
+				}
+				for child in node.children.iter_mut().rev() {
+					steps.push(Step::Visit(child, white_space, rendered));
+				}
+			}
+			_ => {
+				for child in node.children.iter_mut().rev() {
+					steps.push(Step::Visit(child, inherited, rendered));
+				}
+			}
+		}
+	}
+
+	fn break_line(&mut self) {
+		self.line_start = true;
+		self.pending_space = false;
+	}
+
+	fn process(&mut self, text: &str, white_space: WhiteSpace) -> String {
+		let mut processed = String::new();
+		for c in text.chars() {
+			match c {
+				'\n' if white_space.preserves_newlines() => {
+					processed.push('\n');
+					self.break_line();
+				}
+				' ' | '\t' | '\n' if white_space.collapses_spaces() => {
+					if !self.line_start {
+						self.pending_space = true;
+					}
+				}
+				_ => {
+					if self.pending_space {
+						processed.push(' ');
+						self.pending_space = false;
+					}
+					processed.push(c);
+					self.line_start = false;
+				}
+			}
+		}
+		processed
+	}
+}
@@ -1,6 +1,6 @@
 use cairo::{Context, FontSlant, FontWeight};
 
-use crate::parse::{style::StyledNode};
+use crate::parse::{dom::{NodeId, NodeType}, style::StyledNode};
 
 use super::visuals::Block;
 
@@ -52,18 +52,50 @@ pub struct Renderer<'a> {
     context: &'a Context,
     bounds: Bounds,
     coords: Coordinates,
+    regions: Vec<Region>,
+}
+
+/// The part of the page an element was painted in, from where it starts
+/// across to the right edge.
+#[derive(Debug)]
+struct Region {
+    node: NodeId,
+    left: f64,
+    top: f64,
+    bottom: f64,
+}
+
+/// Where each element was painted, for finding the element under the
+/// pointer.
+#[derive(Debug, Default)]
+pub struct HitRegions {
+    regions: Vec<Region>,
+}
+
+impl HitRegions {
+    /// The innermost element painted at `x`, `y`.
+    pub fn node_at(&self, x: f64, y: f64) -> Option<NodeId> {
+        // Elements are painted before what's inside them.
+        self.regions
+            .iter()
+            .rev()
+            .find(|region| x >= region.left && y >= region.top && y < region.bottom)
+            .map(|region| region.node)
+    }
 }
 
 impl Renderer<'_> {
-    pub fn new(context: &Context, width: i32, height: i32) -> Renderer {
+    pub fn new(context: &Context, width: i32, height: i32) -> Renderer<'_> {
         Renderer {
             context,
             bounds: Bounds { width, height },
             coords: Coordinates { x: 0.0, y: 0.0 },
+            regions: Vec::new(),
         }
     }
 
-    pub fn draw(&mut self, root_node: StyledNode) {
+    /// Paints the page, returning where each element ended up.
+    pub fn draw(mut self, root_node: StyledNode) -> HitRegions {
         self.context.set_source_rgb(1.0, 1.0, 1.0);
         self.context.paint().expect("Paint failed!");
 
@@ -71,26 +103,51 @@ impl Renderer<'_> {
             .select_font_face("Sans", FontSlant::Normal, FontWeight::Normal);
 
         self.walk_node_tree(&root_node);
+        HitRegions { regions: self.regions }
     }
 
-    fn walk_node_tree(&mut self, next_node: &StyledNode) {
-				let painting_block = Block::new(next_node);
-				let block_dimensions = painting_block.dimensions();
-				let last_x = self.coords.x;
-				
-				self.coords.move_down(block_dimensions.inner_box.y + block_dimensions.outer_box.top_y, &self.bounds);
-				self.coords.move_right(block_dimensions.outer_box.left_x, &self.bounds);
-				self.context.move_to(self.coords.x, self.coords.y);
-				
-				painting_block.paint(self.context);
-				
-        if !next_node.children.is_empty() {
-            for child in next_node.children.iter() {
-                self.walk_node_tree(child);
+    fn walk_node_tree(&mut self, root_node: &StyledNode) {
+        let mut steps = vec![Step::Paint(root_node)];
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Paint(next_node) => {
+                    let painting_block = Block::new(next_node);
+                    let block_dimensions = painting_block.dimensions();
+                    let last_x = self.coords.x;
+                    let region = match next_node.node.node_type {
+                        NodeType::Element(_) => {
+                            self.regions.push(Region { node: next_node.id, left: last_x, top: self.coords.y, bottom: self.coords.y });
+                            Some(self.regions.len() - 1)
+                        }
+                        _ => None,
+                    };
+
+                    self.coords.move_down(block_dimensions.inner_box.y + block_dimensions.outer_box.top_y, &self.bounds);
+                    self.coords.move_right(block_dimensions.outer_box.left_x, &self.bounds);
+                    self.context.move_to(self.coords.x, self.coords.y);
+
+                    painting_block.paint(self.context);
+
+                    steps.push(Step::Finish { bottom_y: block_dimensions.outer_box.bottom_y, last_x, region });
+                    steps.extend(next_node.children.iter().rev().map(Step::Paint));
+                }
+                Step::Finish { bottom_y, last_x, region } => {
+                    self.coords.move_down(bottom_y, &self.bounds);
+                    self.coords.reset_x(Some(last_x));
+                    if let Some(region) = region {
+                        self.regions[region].bottom = self.coords.y;
+                    }
+                }
             }
         }
-
-				self.coords.move_down(block_dimensions.outer_box.bottom_y, &self.bounds);
-				self.coords.reset_x(Some(last_x));
     }
 }
+
+/// What's left to do while walking the tree, kept on a stack rather than
+/// recursing so deep trees can't overflow.
+enum Step<'a> {
+    Paint(&'a StyledNode<'a>),
+    /// Moves past a block once its children have been painted, and records
+    /// where it ended in its `region`.
+    Finish { bottom_y: f64, last_x: f64, region: Option<usize> },
+}
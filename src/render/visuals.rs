@@ -51,16 +51,17 @@ impl Block<'_> {
 		
 		pub fn dimensions(&self) -> Dimensions {
 			match self.node.node.node_type {
-				NodeType::Text(_) => Dimensions { outer_box: OuterBox::default(), inner_box: InnerBox::new(&self.visuals) },
+				NodeType::Text(_) if self.node.text.is_some() => {
+					Dimensions { outer_box: OuterBox::default(), inner_box: InnerBox::new(&self.visuals) }
+				}
 				NodeType::Element(_) => Dimensions { outer_box: OuterBox::new(&self.visuals), inner_box: InnerBox::default() },
 				_ => Dimensions::default(),
 			}
 		}
 		
 		fn render_text(&self, context: &Context) -> bool {
-			let node = self.node;
-			match &node.node.node_type {
-					NodeType::Text(content) => {
+			match &self.node.text {
+					Some(content) => {
 							context
 									.show_text(content)
 									.expect("Writing text failed");
@@ -90,7 +91,7 @@ impl VisualRules {
 								Some(Value::ColorValue(color)) => color.clone(),
 								_ => Color::default()
 						},
-						padding: Padding::new(&node),
+						padding: Padding::new(node),
         }
     }
 		